@@ -0,0 +1,388 @@
+//! # Declarative configuration
+//!
+//! An [ExpanderConfig] captures all per-device settings, which are applied at once by
+//! [PCA9570::with_config()](crate::expander::PCA9570::with_config). The config is validated beforehand,
+//! and the output register is written at most once.
+//! ```
+//! use pca9570::config::{ExpanderConfig, Polarity, RetryPolicy, SafeLevel, SafeState};
+//! use pca9570::expander::PinID::{Pin0, Pin1, Pin3};
+//! use pca9570::expander::PCA9570;
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! let config = ExpanderConfig::new(0x24)
+//!     .output(Pin0, false)
+//!     .output(Pin1, false)
+//!     .polarity(Pin1, Polarity::ActiveLow)
+//!     .label(Pin0, "pump")
+//!     .label(Pin1, "valve")
+//!     .input(Pin3)
+//!     .safe_state(SafeState::new().pin(Pin0, SafeLevel::Low).pin(Pin1, SafeLevel::Low))
+//!     .retry_policy(RetryPolicy { retries: 2, verify: true });
+//!
+//! let expander = PCA9570::with_config(SimulatedPCA9570::default(), config).unwrap();
+//! assert!(!expander.is_pin_output_high(Pin1));
+//! assert_eq!(Some("valve"), expander.label(Pin1));
+//! ```
+//! With the `serde` feature enabled, the config can be loaded from files, e.g. by host tools.
+use crate::expander::{Mode, PinID};
+use core::convert::Infallible;
+use core::fmt::{Debug, Display, Formatter};
+
+/// Maximum length of pin labels in bytes
+pub const MAX_LABEL_LEN: usize = 16;
+
+/// Logical polarity of a pin
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Polarity {
+    /// Asserted state is a high output
+    #[default]
+    ActiveHigh,
+
+    /// Asserted state is a low output, e.g. for loads switched to VCC
+    ActiveLow,
+}
+
+/// Level of a single pin in the safe state
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SafeLevel {
+    /// Logical low, i.e. not asserted
+    Low,
+
+    /// Logical high, i.e. asserted
+    High,
+
+    /// Keeps the last value
+    #[default]
+    Hold,
+}
+
+/// Output levels the expander falls back to, s. [PCA9570::apply_safe_state()](crate::expander::PCA9570::apply_safe_state)
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SafeState {
+    /// Levels of the pins, starting with Pin0
+    pub pins: [SafeLevel; 4],
+
+    /// Number of consecutive failed transactions, after which the safe state is written
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub after_errors: Option<u8>,
+}
+
+impl SafeState {
+    /// Returns a safe state holding the last value of all pins
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the level of the given pin
+    pub fn pin(mut self, id: PinID, level: SafeLevel) -> Self {
+        self.pins[id as usize] = level;
+        self
+    }
+
+    /// Writes the safe state after the given number of consecutive failed transactions
+    /// This is done once per series of errors, as the bus is likely to fail again.
+    pub fn after_errors(mut self, errors: u8) -> Self {
+        self.after_errors = Some(errors);
+        self
+    }
+
+    /// Returns the level of the given pin
+    pub fn level(&self, id: PinID) -> SafeLevel {
+        self.pins[id as usize]
+    }
+}
+
+/// Policy for writing the output register
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryPolicy {
+    /// Number of times a failed write is repeated
+    pub retries: u8,
+
+    /// Reads back the output register after each write and fails on a mismatch
+    pub verify: bool,
+}
+
+/// Pin label, e.g. for diagnostics
+///
+/// Labels are stored inline, so they are limited to [MAX_LABEL_LEN] bytes. They must not be empty or
+/// contain whitespace.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Label {
+    bytes: [u8; MAX_LABEL_LEN],
+    len: u8,
+}
+
+impl Label {
+    /// Returns the label, or None if the text is not a valid label
+    pub fn new(text: &str) -> Option<Self> {
+        if text.is_empty() || text.len() > MAX_LABEL_LEN || text.contains(char::is_whitespace) {
+            return None;
+        }
+
+        let mut bytes = [0x0; MAX_LABEL_LEN];
+        bytes[..text.len()].copy_from_slice(text.as_bytes());
+
+        Some(Self {
+            bytes,
+            len: text.len() as u8,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Just created from valid strings
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+}
+
+impl Debug for Label {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for Label {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Label {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}", self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Label {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Label {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct LabelVisitor;
+
+        impl serde::de::Visitor<'_> for LabelVisitor {
+            type Value = Label;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+                write!(f, "a label of up to {} bytes without whitespace", MAX_LABEL_LEN)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Label, E> {
+                Label::new(text).ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(text), &self))
+            }
+        }
+
+        deserializer.deserialize_str(LabelVisitor)
+    }
+}
+
+/// Settings of a single pin
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PinConfig {
+    pub mode: Mode,
+
+    /// Logical initial output, None keeps the power-on state
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub initial: Option<bool>,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub polarity: Polarity,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub label: Option<Label>,
+}
+
+impl Default for PinConfig {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Input,
+            initial: None,
+            polarity: Polarity::default(),
+            label: None,
+        }
+    }
+}
+
+/// Per-device settings applied by [PCA9570::with_config()](crate::expander::PCA9570::with_config)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExpanderConfig {
+    address: u8,
+
+    pins: [PinConfig; 4],
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) safe_state: Option<SafeState>,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) retry_policy: RetryPolicy,
+
+    /// Pin of the first label rejected by [ExpanderConfig::label()]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    invalid_label: Option<PinID>,
+}
+
+impl ExpanderConfig {
+    /// Returns a config with all pins in input mode, matching [PCA9570::new()](crate::expander::PCA9570::new)
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            pins: [PinConfig::default(); 4],
+            safe_state: None,
+            retry_policy: RetryPolicy::default(),
+            invalid_label: None,
+        }
+    }
+
+    /// Switches the given pin to output mode with the given logical initial state
+    pub fn output(mut self, id: PinID, is_high: bool) -> Self {
+        self.pins[id as usize].mode = Mode::Output;
+        self.pins[id as usize].initial = Some(is_high);
+        self
+    }
+
+    /// Switches the given pin to input mode
+    pub fn input(mut self, id: PinID) -> Self {
+        self.pins[id as usize].mode = Mode::Input;
+        self.pins[id as usize].initial = None;
+        self
+    }
+
+    /// Sets the logical polarity of the given pin
+    pub fn polarity(mut self, id: PinID, polarity: Polarity) -> Self {
+        self.pins[id as usize].polarity = polarity;
+        self
+    }
+
+    /// Sets the label of the given pin
+    /// Invalid labels are reported by [validate()](ExpanderConfig::validate).
+    pub fn label(mut self, id: PinID, text: &str) -> Self {
+        match Label::new(text) {
+            Some(label) => self.pins[id as usize].label = Some(label),
+            None => self.invalid_label = self.invalid_label.or(Some(id)),
+        }
+
+        self
+    }
+
+    /// Sets the safe state
+    pub fn safe_state(mut self, safe_state: SafeState) -> Self {
+        self.safe_state = Some(safe_state);
+        self
+    }
+
+    /// Sets the policy for writing the output register
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Returns the settings of the given pin
+    pub fn pin(&self, id: PinID) -> &PinConfig {
+        &self.pins[id as usize]
+    }
+
+    /// Checks the config for invalid settings and combinations
+    pub fn validate(&self) -> Result<(), ConfigError<Infallible>> {
+        if self.address > 0x7F {
+            return Err(ConfigError::InvalidAddress(self.address));
+        }
+
+        if let Some(id) = self.invalid_label {
+            return Err(ConfigError::InvalidLabel(id));
+        }
+
+        for (index, pin) in self.pins.iter().enumerate() {
+            let id = PIN_IDS[index];
+
+            if pin.mode == Mode::Input && pin.initial.is_some() {
+                return Err(ConfigError::OutputOnInput(id));
+            }
+
+            let is_duplicate =
+                pin.label.is_some() && self.pins[..index].iter().any(|other| other.label == pin.label);
+            if is_duplicate {
+                return Err(ConfigError::DuplicateLabel(id));
+            }
+
+            let level = self.safe_state.map(|state| state.pins[index]).unwrap_or_default();
+            if pin.mode == Mode::Input && level != SafeLevel::Hold {
+                return Err(ConfigError::SafeStateOnInput(id));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const PIN_IDS: [PinID; 4] = [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3];
+
+/// Error of [PCA9570::with_config()](crate::expander::PCA9570::with_config)
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError<E> {
+    /// Address exceeds the 7-bit range
+    InvalidAddress(u8),
+
+    /// Label of the given pin is empty, too long or contains whitespace
+    InvalidLabel(PinID),
+
+    /// Label of the given pin is already used by a lower pin
+    DuplicateLabel(PinID),
+
+    /// Initial output is set for the given pin in input mode
+    OutputOnInput(PinID),
+
+    /// Safe state drives the given pin in input mode
+    SafeStateOnInput(PinID),
+
+    /// Writing the initial output state failed
+    BusError(E),
+}
+
+impl ConfigError<Infallible> {
+    /// Converts a validation error
+    pub(crate) fn widen<E>(self) -> ConfigError<E> {
+        match self {
+            ConfigError::InvalidAddress(address) => ConfigError::InvalidAddress(address),
+            ConfigError::InvalidLabel(id) => ConfigError::InvalidLabel(id),
+            ConfigError::DuplicateLabel(id) => ConfigError::DuplicateLabel(id),
+            ConfigError::OutputOnInput(id) => ConfigError::OutputOnInput(id),
+            ConfigError::SafeStateOnInput(id) => ConfigError::SafeStateOnInput(id),
+            ConfigError::BusError(error) => match error {},
+        }
+    }
+}
+
+impl<E: Display> Display for ConfigError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ConfigError::InvalidAddress(address) => write!(f, "InvalidAddress({:#04x})", address),
+            ConfigError::InvalidLabel(id) => write!(f, "InvalidLabel({:?})", id),
+            ConfigError::DuplicateLabel(id) => write!(f, "DuplicateLabel({:?})", id),
+            ConfigError::OutputOnInput(id) => write!(f, "OutputOnInput({:?})", id),
+            ConfigError::SafeStateOnInput(id) => write!(f, "SafeStateOnInput({:?})", id),
+            ConfigError::BusError(error) => error.fmt(f),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! # Diagnostics
+//!
+//! [PCA9570::diagnostics()](crate::expander::PCA9570::diagnostics) returns a [Diagnostics] snapshot of the
+//! driver state, e.g. for reports from the field. Besides [Debug], it renders a per-pin table via [Display],
+//! which does not require `alloc`.
+//! ```
+//! use pca9570::expander::PCA9570;
+//! use pca9570::expander::PinID::Pin1;
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+//! expander.set_state_all(false).unwrap();
+//! expander.set_state(Pin1, true);
+//!
+//! let diagnostics = expander.diagnostics();
+//! assert!(diagnostics.is_output_stale());
+//! assert!(diagnostics.is_input_unknown());
+//!
+//! println!("{}", diagnostics);
+//! ```
+//! Output:
+//! ```text
+//! PCA9570 at 0x24
+//! Pin  Mode    Output  Device  Input
+//! P0   Input   low     low     ?
+//! P1   Input   high    low     ?
+//! P2   Input   low     low     ?
+//! P3   Input   low     low     ?
+//! Output stale: yes, last write failed: no, pending changes: 0
+//! ```
+use crate::expander::{Mode, PinID};
+#[cfg(feature = "stats")]
+use crate::stats::Stats;
+use core::fmt::{Display, Formatter};
+
+/// Snapshot of the driver state
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Diagnostics {
+    /// 7-bit I2C address
+    pub address: u8,
+
+    /// Cached output register, which is written on the next update
+    pub output: u8,
+
+    /// Value of the last successful write of the output register, None if not written yet
+    pub device_output: Option<u8>,
+
+    /// Configuration register, set bits are pins in input mode
+    pub configuration: u8,
+
+    /// Cached input register, None if not read yet
+    pub input: Option<u8>,
+
+    /// Timestamp of the last successful input refresh, if a clock is set
+    pub input_refreshed_at: Option<u64>,
+
+    /// True if the last write of the output register failed
+    pub last_write_failed: bool,
+
+    /// Number of changes of pins in auto-flush access mode, which are not written yet
+    pub pending_changes: u8,
+
+    /// Transaction counters
+    #[cfg(feature = "stats")]
+    pub stats: Stats,
+}
+
+impl Diagnostics {
+    /// Returns the mode of the given pin
+    pub fn mode(&self, id: PinID) -> Mode {
+        match Self::bit(self.configuration, id) {
+            true => Mode::Input,
+            false => Mode::Output,
+        }
+    }
+
+    /// Returns true if the device output is unknown, as the output register was not written yet
+    pub fn is_output_unknown(&self) -> bool {
+        self.device_output.is_none()
+    }
+
+    /// Returns true if the device output may differ from the cached output register
+    pub fn is_output_stale(&self) -> bool {
+        self.last_write_failed || self.device_output != Some(self.output)
+    }
+
+    /// Returns true if the input state is unknown, as the input register was not read yet
+    pub fn is_input_unknown(&self) -> bool {
+        self.input.is_none()
+    }
+
+    fn bit(value: u8, id: PinID) -> bool {
+        value & (1 << id as u8) != 0
+    }
+
+    fn level(value: Option<u8>, id: PinID) -> &'static str {
+        match value.map(|value| Self::bit(value, id)) {
+            Some(true) => "high",
+            Some(false) => "low",
+            None => "?",
+        }
+    }
+
+    fn yes_no(value: bool) -> &'static str {
+        if value {
+            "yes"
+        } else {
+            "no"
+        }
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "PCA9570 at {:#04x}", self.address)?;
+        writeln!(f, "{:<5}{:<8}{:<8}{:<8}Input", "Pin", "Mode", "Output", "Device")?;
+
+        for id in [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3] {
+            let mode = match self.mode(id) {
+                Mode::Output => "Output",
+                Mode::Input => "Input",
+            };
+
+            writeln!(
+                f,
+                "P{:<4}{:<8}{:<8}{:<8}{}",
+                id as u8,
+                mode,
+                Self::level(Some(self.output), id),
+                Self::level(self.device_output, id),
+                Self::level(self.input, id)
+            )?;
+        }
+
+        write!(
+            f,
+            "Output stale: {}, last write failed: {}, pending changes: {}",
+            Self::yes_no(self.is_output_stale()),
+            Self::yes_no(self.last_write_failed),
+            self.pending_changes
+        )?;
+
+        if let Some(timestamp) = self.input_refreshed_at {
+            write!(f, "\nInput refreshed at: {}", timestamp)?;
+        }
+
+        #[cfg(feature = "stats")]
+        write!(
+            f,
+            "\nWrites: {}, reads: {}, write errors: {}, read errors: {}",
+            self.stats.writes, self.stats.reads, self.stats.write_errors, self.stats.read_errors
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! # Emergency stop
+//!
+//! In a `#[panic_handler]` the regular API may deadlock, as the pins guard may still be held by the
+//! panicking code. An [EmergencyStop] bypasses the driver and its guards entirely. It owns a separate bus
+//! handle, e.g. a stolen I2C peripheral, and writes a predefined byte with a single transaction.
+//!
+//! The byte is captured up front by [PCA9570::emergency_stop()](crate::expander::PCA9570::emergency_stop),
+//! based on the safe state, s. [PCA9570::emergency_value()](crate::expander::PCA9570::emergency_value).
+//! ```ignore
+//! static EMERGENCY_VALUE: AtomicU8 = AtomicU8::new(0x0);
+//!
+//! // During setup
+//! EMERGENCY_VALUE.store(expander.emergency_value(), Ordering::Relaxed);
+//!
+//! #[panic_handler]
+//! fn panic(_info: &PanicInfo) -> ! {
+//!     let bus = unsafe { steal_i2c() };
+//!     let mut stop = EmergencyStop::new(bus, 0x24, EMERGENCY_VALUE.load(Ordering::Relaxed));
+//!     let _ = unsafe { stop.trigger() };
+//!
+//!     loop {}
+//! }
+//! ```
+//! Host based example, triggering while a guard is held:
+//! ```
+//! use core::cell::RefCell;
+//! use pca9570::config::{SafeLevel, SafeState};
+//! use pca9570::expander::PinID::{Pin0, Pin1};
+//! use pca9570::expander::PCA9570;
+//! use pca9570::guard::{LockFreeGuard, RefGuard};
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! let simulator = SimulatedPCA9570::default();
+//! let mut expander = PCA9570::new(&simulator, 0x24);
+//! expander.set_safe_state(SafeState::new().pin(Pin0, SafeLevel::Low).pin(Pin1, SafeLevel::Low)).unwrap();
+//!
+//! // Shared reference to the simulator as second bus handle
+//! let mut stop = expander.emergency_stop(&simulator);
+//!
+//! let guard = LockFreeGuard::new(RefCell::new(&mut expander));
+//! guard
+//!     .access(|_| {
+//!         // Safety: Nothing else is using the bus right now
+//!         unsafe { stop.trigger() }.unwrap();
+//!     })
+//!     .unwrap();
+//!
+//! assert_eq!([false, false, true, true], simulator.pins());
+//! ```
+use embedded_hal::blocking::i2c::{SevenBitAddress, Write};
+
+/// Writes a predefined byte to the output register, bypassing the driver, s. [module](crate::emergency)
+#[derive(Debug)]
+pub struct EmergencyStop<B: Write> {
+    bus: B,
+
+    address: SevenBitAddress,
+
+    value: u8,
+}
+
+impl<B: Write> EmergencyStop<B> {
+    /// Creates the emergency stop based on a separate bus handle
+    pub fn new(bus: B, address: SevenBitAddress, value: u8) -> Self {
+        Self { bus, address, value }
+    }
+
+    /// Writes the predefined byte using a single transaction
+    ///
+    /// Neither locks are taken nor is memory allocated, so this is usable from panic handlers.
+    ///
+    /// # Safety
+    /// The write bypasses the driver and its guards. So it may interfere with a transaction in progress, e.g.
+    /// if panicking while the driver is accessing the bus. The caller must ensure the bus handle is usable,
+    /// e.g. by resetting the I2C peripheral beforehand. Afterwards, the cached state of the driver does not
+    /// match the device anymore, so the driver should not be used anymore.
+    pub unsafe fn trigger(&mut self) -> Result<(), B::Error> {
+        self.bus.write(self.address, &[self.value])
+    }
+
+    /// Returns the byte written when triggered
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+
+    /// Returns the bus handle
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+}
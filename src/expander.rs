@@ -10,21 +10,21 @@
 //! [PCA9570] instance is created using a I2CBus implementing the I2C traits of
 //! [embedded-hal](https://docs.rs/embedded-hal/latest/embedded_hal/blocking/i2c/index.html).
 //!```
-//! use pca9570::example::DummyI2CBus;
+//! use pca9570::sim::SimulatedPCA9570;
 //! use pca9570::expander::PCA9570;
 //!
-//! let i2c_bus = DummyI2CBus::default();
+//! let i2c_bus = SimulatedPCA9570::default();
 //! // Assuming I2C device address 0x24
 //! let expander = PCA9570::new(i2c_bus, 0x24);
 //! ```
 //! ## Changing mode
 //! ```
-//!# use pca9570::example::DummyI2CBus;
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::Mode::{Input, Output};
 //!# use pca9570::expander::PCA9570;
-//!# use pca9570::expander::PinID::{Pin2, Pin4};
+//!# use pca9570::expander::PinID::{Pin1, Pin2};
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //!#
 //! // Switch Pin1 to input mode
@@ -35,11 +35,11 @@
 //! ```
 //! ## Reading input state
 //! ```
-//!# use pca9570::example::DummyI2CBus;
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::PCA9570;
 //!# use pca9570::expander::PinID::Pin1;
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //!#
 //! expander.refresh_input_state().unwrap();
@@ -49,12 +49,12 @@
 //! ```
 //! ## Setting output state
 //! ```
-//!# use pca9570::example::DummyI2CBus;
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::Mode::Output;
 //!# use pca9570::expander::PCA9570;
 //!# use pca9570::expander::PinID::Pin1;
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //!#
 //! expander.set_mode(Pin1, Output);
@@ -65,39 +65,139 @@
 //! let is_high = expander.is_pin_output_high(Pin1);
 //! assert!(is_high);
 //! ```
-//! ## Invert input polarity
-//! PCA9570 has built-in hardware support for inverting input state. See [datasheet](<https://www.ti.com/lit/ds/symlink/pca9570.pdf?ts=1649342250975>)
-//! for more details.
+//! ## Snapshots
+//! Output state and pin modes can be saved and restored, e.g. as JSON or TOML using the `serde` feature.
 //! ```
-//!# use pca9570::example::DummyI2CBus;
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::PCA9570;
-//!# use pca9570::expander::PinID::{Pin1, Pin3};
+//!# use pca9570::expander::PinID::Pin1;
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //!#
-//! expander.reverse_polarity(Pin3, true).unwrap();
+//! let snapshot = expander.snapshot();
+//!
+//! expander.set_state_all(false).unwrap();
+//! assert!(!expander.is_pin_output_high(Pin1));
+//!
+//! // Restoring requires a single write
+//! expander.restore(snapshot).unwrap();
+//! assert!(expander.is_pin_output_high(Pin1));
+//! ```
+//! ## Polarity
+//! PCA9570 has no polarity register, so polarity is inverted in software. For active-low pins, all
+//! states are logical ones, i.e. high means asserted. This applies to [set_state()](PCA9570::set_state),
+//! [is_pin_output_high()](PCA9570::is_pin_output_high), [is_pin_input_high()](PCA9570::is_pin_input_high),
+//! the pin traits, snapshots and the safe state. Only the raw registers, e.g.
+//! [output_as_value()](PCA9570::output_as_value), keep the electrical levels.
+//! ```
+//!# use pca9570::sim::SimulatedPCA9570;
+//!# use pca9570::config::Polarity;
+//!# use pca9570::expander::PCA9570;
+//!# use pca9570::expander::PinID::Pin3;
+//!#
+//!# let i2c_bus = SimulatedPCA9570::default();
+//!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
+//!#
+//! expander.set_polarity(Pin3, Polarity::ActiveLow);
+//!
+//! expander.set_state(Pin3, true);
+//! expander.write_output_state().unwrap();
+//!
+//! assert!(expander.is_pin_output_high(Pin3));
+//! assert_eq!(0b1111_0111, expander.output_as_value());
+//! ```
+//! ## Current budget
+//! The load current of each pin is counted while the pin is asserted, taking its polarity into account. As the
+//! outputs are open-drain, loads connected to the supply draw current while the output is low, so these pins
+//! need to be declared [active low](Polarity::ActiveLow). Writes exceeding the package budget are rejected with
+//! [OutputError::CurrentBudget] without touching the bus.
+//! ```
+//!# use pca9570::sim::SimulatedPCA9570;
+//!# use pca9570::config::Polarity;
+//!# use pca9570::expander::{OutputError, PCA9570};
+//!# use pca9570::expander::PinID::{Pin0, Pin1};
+//!#
+//!# let i2c_bus = SimulatedPCA9570::default();
+//!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
+//!#
+//! expander.set_polarity(Pin0, Polarity::ActiveLow);
+//! expander.set_polarity(Pin1, Polarity::ActiveLow);
+//! expander.set_state_all(false).unwrap();
+//! expander.set_load_current(Pin0, 20);
+//! expander.set_load_current(Pin1, 15);
+//! expander.set_current_budget(30);
+//!
+//! // Pin0 is driven low, so its load draws current
+//! expander.set_state(Pin0, true);
+//! expander.write_output_state().unwrap();
+//! assert_eq!(0b0000_0010, expander.output_as_value());
+//! assert_eq!(20, expander.load_current());
+//!
+//! expander.set_state(Pin1, true);
+//! assert_eq!(
+//!     Err(OutputError::CurrentBudget { load: 35, budget: 30 }),
+//!     expander.write_output_state()
+//! );
+//! ```
+//! ## Safe state
+//! The safe state is written when the driver or its pins container is dropped, when calling
+//! [destroy()](PCA9570::destroy) and optionally after consecutive bus errors. Pins on hold keep their last
+//! value. Writing the safe state bypasses interlocks and the current budget, so falling back is never blocked.
+//! ```
+//!# use pca9570::sim::SimulatedPCA9570;
+//!# use pca9570::config::{SafeLevel, SafeState};
+//!# use pca9570::expander::PCA9570;
+//!# use pca9570::expander::PinID::{Pin0, Pin1};
+//!#
+//! let simulator = SimulatedPCA9570::default();
+//! let mut expander = PCA9570::new(&simulator, 0x24);
+//! expander.set_safe_state(SafeState::new().pin(Pin0, SafeLevel::Low).pin(Pin1, SafeLevel::Low)).unwrap();
+//!
+//! expander.set_state_all(true).unwrap();
+//! drop(expander);
+//!
+//! assert_eq!([false, false, true, true], simulator.pins());
 //! ```
 
+use crate::config::{ConfigError, ExpanderConfig, Label, Polarity, RetryPolicy, SafeLevel, SafeState};
+use crate::diagnostics::Diagnostics;
+use crate::emergency::EmergencyStop;
+#[cfg(all(feature = "portable-atomic", any(feature = "spin", feature = "cortex-m")))]
+use crate::guard::AtomicShadowGuard;
 #[cfg(feature = "cortex-m")]
 use crate::guard::CsMutexGuard;
 use crate::guard::LockFreeGuard;
 #[cfg(feature = "spin")]
 use crate::guard::SpinGuard;
+use crate::interlock::{Interlock, MAX_INTERLOCKS};
+use crate::observer::{Observer, ObserverRef, TransactionError};
+#[cfg(feature = "embedded-storage")]
+use crate::persist::{OutputStore, StoreError, StoredState};
 use crate::pins::Pins;
-#[cfg(feature = "alloc")]
-use alloc::string::{String, ToString};
+use crate::scene::{Scene, SceneError, MAX_SCENES};
+#[cfg(feature = "stats")]
+use crate::stats::Stats;
+use crate::watchdog::{Watchdog, WatchdogState, WatchdogStatus};
+use crate::wear::{CycleCounters, ToggleAction, ToggleLimit};
 use bitmaps::Bitmap;
 use core::cell::RefCell;
-use core::fmt::{Debug, Formatter};
+use core::fmt::{Debug, Display, Formatter};
 #[cfg(feature = "cortex-m")]
 use cortex_m::interrupt::Mutex as CsMutex;
 use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
+#[cfg(feature = "embedded-storage")]
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
 #[cfg(feature = "spin")]
 use spin::Mutex as SpinMutex;
 
+/// Mask of the used bits of the output register
+pub(crate) const OUTPUT_MASK: u8 = 0b0000_1111;
+
 /// GPIO pin ID.
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PinID {
     Pin0 = 0,
     Pin1 = 1,
@@ -106,18 +206,57 @@ pub enum PinID {
 }
 
 /// GPIO mode
-#[derive(PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Mode {
     Output,
     Input,
 }
 
+/// User supplied clock, returning a monotonic timestamp
+/// The unit of the ticks is defined by the user, e.g. milliseconds since boot.
+pub type Clock = fn() -> u64;
+
+/// Result of restoring the saved state, s. [PCA9570::with_store()]
+#[cfg(feature = "embedded-storage")]
+pub type RestoreResult<F, B> = Result<(), StoreError<F, OutputError<B>>>;
+
+/// Policy for automatically writing changes of pins in [auto-flush access mode](crate::pins::AutoFlushMode)
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AutoFlushPolicy {
+    /// Writes the output register once the given number of changes is pending
+    pub max_changes: Option<u8>,
+
+    /// Writes the output register once the first pending change is older than the given number of clock ticks
+    /// Requires a clock, s. [PCA9570::set_clock()]
+    ///
+    /// The age is checked on each pin access and input refresh. If the driver may stay idle, e.g. after the
+    /// last change, [Pins::poll_auto_flush()](crate::pins::Pins::poll_auto_flush) or [PCA9570::flush_if_due()]
+    /// needs to be called periodically, e.g. from a timer.
+    pub max_age: Option<u64>,
+}
+
+/// Snapshot of output state and pin modes, s. [PCA9570::snapshot()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutputSnapshot {
+    /// Output states, starting with Pin0
+    pub outputs: [bool; 4],
+
+    /// Pin modes, starting with Pin0
+    pub modes: [Mode; 4],
+}
+
 /// Abstraction of [PCA9570](<https://www.ti.com/lit/ds/symlink/pca9570.pdf?ts=1649342250975>) I/O expander
 pub struct PCA9570<B>
 where
     B: Write<SevenBitAddress> + Read<SevenBitAddress>,
 {
-    bus: B,
+    /// I2C bus, just taken by [PCA9570::destroy()]
+    bus: Option<B>,
 
     /// I2C slave address 0x24 + R/!W bit
     address: u8,
@@ -130,6 +269,93 @@ where
 
     /// Configuration register
     configuration: Bitmap<8>,
+
+    /// User supplied clock
+    clock: Option<Clock>,
+
+    /// Policy for pins in auto-flush access mode
+    auto_flush: AutoFlushPolicy,
+
+    /// Number of changes recorded since the output register was written the last time
+    pending_changes: u8,
+
+    /// Timestamp of the first change recorded since the output register was written the last time
+    pending_since: Option<u64>,
+
+    /// Transaction counters
+    #[cfg(feature = "stats")]
+    stats: Stats,
+
+    /// Value of the last successful write of the output register, the power-on cache before
+    committed: u8,
+
+    /// True if the output register was written successfully at least once
+    output_confirmed: bool,
+
+    /// True if the last write of the output register failed
+    last_write_failed: bool,
+
+    /// True if the input register was read successfully at least once
+    input_known: bool,
+
+    /// Timestamp of the last successful refresh of the input register, if a clock is set
+    input_refreshed_at: Option<u64>,
+
+    /// Attached observer
+    observer: Option<ObserverRef<B>>,
+
+    /// Set bits are pins with active-low logical polarity
+    polarity: u8,
+
+    /// Policy for writing the output register
+    retry_policy: RetryPolicy,
+
+    /// Output levels to fall back to
+    safe_state: Option<SafeState>,
+
+    /// Pin labels, starting with Pin0
+    labels: [Option<Label>; 4],
+
+    /// Registered interlock rules
+    interlocks: [Option<Interlock>; MAX_INTERLOCKS],
+
+    /// Number of failed transactions since the last successful one
+    consecutive_errors: u8,
+
+    /// True if the safe state was written due to the current series of errors
+    fell_back: bool,
+
+    /// Watchdog settings and state, if enabled
+    watchdog: Option<WatchdogState>,
+
+    /// Load current of the pins in milliamps, starting with Pin0
+    load_currents: [u16; 4],
+
+    /// Maximum combined load current in milliamps
+    current_budget: Option<u16>,
+
+    /// Switching cycles of the pins, starting with Pin0
+    cycles: [u32; 4],
+
+    /// Timestamp of the last transition of the pins, starting with Pin0
+    toggled_at: [Option<u64>; 4],
+
+    /// Minimum interval between two transitions of the same pin
+    toggle_limit: Option<ToggleLimit>,
+
+    /// Registered scenes
+    scenes: [Option<Scene>; MAX_SCENES],
+}
+
+/// All slots of a fixed-capacity table are taken, e.g. the interlock rules
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CapacityError;
+
+impl Display for CapacityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str("CapacityError")
+    }
 }
 
 /// Wrapped I2C error when refreshing input state
@@ -139,70 +365,229 @@ pub enum RefreshInputError<B: Write + Read<u8>> {
     ReadError(<B as Read>::Error),
 }
 
+/// Error when writing the output register
+pub enum OutputError<B: Write + Read<u8>> {
+    /// Writing the output register failed
+    WriteError(<B as Write>::Error),
+
+    /// Reading back the output register for verification failed, s. [RetryPolicy::verify]
+    ReadError(<B as Read>::Error),
+
+    /// Output register read back differs from the written value, s. [RetryPolicy::verify]
+    VerifyMismatch { expected: u8, actual: u8 },
+
+    /// Write was rejected without touching the bus, as it violates the given interlock rule
+    Interlock(Interlock),
+
+    /// Write was rejected without touching the bus, as a watchdog fault is latched
+    WatchdogFault,
+
+    /// Write was rejected without touching the bus, as the combined load current in milliamps would exceed
+    /// the budget, s. [PCA9570::set_current_budget()]
+    CurrentBudget { load: u16, budget: u16 },
+
+    /// Write was rejected without touching the bus, as the pin was toggled too fast, s.
+    /// [PCA9570::set_toggle_limit()]
+    ToggleTooFast(PinID),
+}
+
 impl<B> PCA9570<B>
 where
     B: Write<SevenBitAddress> + Read<SevenBitAddress>,
 {
     pub fn new(bus: B, address: u8) -> Self {
         let mut expander = Self {
-            bus,
+            bus: Some(bus),
             address,
             input: Bitmap::<8>::new(),
             output: Bitmap::<8>::new(),
             configuration: Bitmap::<8>::new(),
+            clock: None,
+            auto_flush: AutoFlushPolicy::default(),
+            pending_changes: 0,
+            pending_since: None,
+            #[cfg(feature = "stats")]
+            stats: Stats::default(),
+            committed: 0x0,
+            output_confirmed: false,
+            last_write_failed: false,
+            input_known: false,
+            input_refreshed_at: None,
+            observer: None,
+            polarity: 0x0,
+            retry_policy: RetryPolicy::default(),
+            safe_state: None,
+            labels: [None; 4],
+            interlocks: [None; MAX_INTERLOCKS],
+            consecutive_errors: 0,
+            fell_back: false,
+            watchdog: None,
+            load_currents: [0; 4],
+            current_budget: None,
+            cycles: [0; 4],
+            toggled_at: [None; 4],
+            toggle_limit: None,
+            scenes: [None; MAX_SCENES],
         };
 
         expander.output.invert();
         expander.configuration.invert();
+        expander.committed = expander.output_as_value();
 
         expander
     }
 
-    // Destroys the driver and returns the I2C bus
-    pub fn destroy(self) -> B {
-        self.bus
+    /// Creates the driver based on the given config, s. [config module](crate::config)
+    ///
+    /// The config is validated before touching the bus. Pin modes are only applied to the cached
+    /// configuration register, so the output register is written once if any pin has an initial output,
+    /// otherwise the bus is not accessed at all. If this write fails, the bus is dropped without writing the
+    /// safe state.
+    pub fn with_config(bus: B, config: ExpanderConfig) -> Result<Self, ConfigError<OutputError<B>>> {
+        config.validate().map_err(ConfigError::widen)?;
+
+        let mut expander = Self::new(bus, config.address());
+        expander.retry_policy = config.retry_policy;
+        expander.safe_state = config.safe_state;
+
+        let mut has_initial_output = false;
+
+        for id in [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3] {
+            let pin = config.pin(id);
+
+            expander.configuration.set(id as usize, pin.mode.into());
+            expander.labels[id as usize] = pin.label;
+            expander.set_polarity(id, pin.polarity);
+
+            if let Some(is_high) = pin.initial {
+                expander.set_state(id, is_high);
+                has_initial_output = true;
+            }
+        }
+
+        if has_initial_output {
+            if let Err(error) = expander.write_output_state() {
+                // Nothing was written successfully, so the safe state is not written to the failing bus on drop
+                expander.bus = None;
+                return Err(ConfigError::BusError(error));
+            }
+        }
+
+        Ok(expander)
+    }
+
+    /// Creates the driver and restores the saved state, s. [persist module](crate::persist)
+    ///
+    /// If a state was saved, the output register is written once. Otherwise the bus is not accessed at all.
+    /// The driver is returned in any case, so an unreadable record, e.g. torn by a power loss while saving,
+    /// does not prevent booting. The driver then keeps the power-on state, and the error is returned alongside.
+    #[cfg(feature = "embedded-storage")]
+    pub fn with_store<F: ReadNorFlash>(
+        bus: B,
+        address: u8,
+        store: &mut OutputStore<F>,
+    ) -> (Self, RestoreResult<F::Error, B>) {
+        let mut expander = Self::new(bus, address);
+
+        let result = match store.load() {
+            Ok(Some(state)) => expander.restore_stored(state).map_err(StoreError::BusError),
+            Ok(None) => Ok(()),
+            Err(error) => Err(error.widen()),
+        };
+
+        (expander, result)
+    }
+
+    /// Destroys the driver and returns the I2C bus
+    /// The safe state is written beforehand, if configured. Errors are ignored, as the bus is returned anyway.
+    pub fn destroy(mut self) -> B {
+        let _ = self.apply_safe_state();
+        self.bus.take().expect("Bus already taken")
     }
 
     /// Returns a pins container without using any locks
     /// This is the most efficient way of using individual pins
     /// The downside is, that these pins are neither Send or Sync, so can only be used in single-threaded
     /// and interrupt-free applications
-    pub fn pins(&mut self) -> Pins<B, LockFreeGuard<B>> {
+    pub fn pins(&mut self) -> Pins<B, LockFreeGuard<'_, B>> {
         Pins::new(LockFreeGuard::new(RefCell::new(self)))
     }
 
     /// Returns a pins container using Mutex based on critical sections
     /// Individual pins can be used across threads and interrupts, as long just running on a single core
     #[cfg(feature = "cortex-m")]
-    pub fn pins_cs_mutex(&mut self) -> Pins<B, CsMutexGuard<B>> {
+    pub fn pins_cs_mutex(&mut self) -> Pins<B, CsMutexGuard<'_, B>> {
         Pins::new(CsMutexGuard::new(CsMutex::new(RefCell::new(self))))
     }
 
+    /// Returns a pins container using Mutex based on critical sections, while keeping the shadow output
+    /// register in an atomic. See [AtomicShadowGuard] for more details.
+    #[cfg(all(feature = "cortex-m", feature = "portable-atomic"))]
+    pub fn pins_atomic_cs_mutex(&mut self) -> Pins<B, AtomicShadowGuard<B, CsMutexGuard<'_, B>>> {
+        Pins::new(AtomicShadowGuard::new(CsMutexGuard::new(CsMutex::new(RefCell::new(
+            self,
+        )))))
+    }
+
     /// Returns a pins container using a spin mutex
     /// This is safe to use across threads and on multi-core applications
     /// However, this requires a system supporting spin mutexes, which are generally only
     /// available on systems with Atomic CAS
     #[cfg(feature = "spin")]
-    pub fn pins_spin_mutex(&mut self) -> Pins<B, SpinGuard<B>> {
+    pub fn pins_spin_mutex(&mut self) -> Pins<B, SpinGuard<'_, B>> {
         Pins::new(SpinGuard::new(SpinMutex::new(RefCell::new(self))))
     }
 
-    /// Switches the given pin to the input/output mode by adjusting the configuration register
-    pub fn set_mode(&mut self, id: PinID, mode: Mode) -> Result<(), <B as Write>::Error> {
-        self.configuration.set(id as usize, mode.into());
-        self.write_conf()
+    /// Returns a pins container using a spin mutex, while keeping the shadow output register in an atomic.
+    /// See [AtomicShadowGuard] for more details.
+    #[cfg(all(feature = "spin", feature = "portable-atomic"))]
+    pub fn pins_atomic_spin_mutex(&mut self) -> Pins<B, AtomicShadowGuard<B, SpinGuard<'_, B>>> {
+        Pins::new(AtomicShadowGuard::new(SpinGuard::new(SpinMutex::new(RefCell::new(
+            self,
+        )))))
+    }
+
+    /// Switches the given pin to the input/output mode by adjusting the cached configuration register
+    /// As the PCA9570 has a single register, a pin switched to input mode is released by writing it high. This
+    /// write is checked like any other output write.
+    pub fn set_mode(&mut self, id: PinID, mode: Mode) -> Result<(), OutputError<B>> {
+        self.set_modes(1 << id as u8, mode)
+    }
+
+    /// Returns the current mode of the given pin, based on the cached configuration register
+    pub fn mode(&self, id: PinID) -> Mode {
+        match self.configuration.get(id as usize) {
+            true => Mode::Input,
+            false => Mode::Output,
+        }
+    }
+
+    /// Switches all pins to output/input mode, s. [PCA9570::set_mode()]
+    pub fn set_mode_all(&mut self, mode: Mode) -> Result<(), OutputError<B>> {
+        self.set_modes(0xFF, mode)
     }
 
-    /// Switches all pins to output/input mode1
-    pub fn set_mode_all(&mut self, mode: Mode) -> Result<(), <B as Write>::Error> {
-        let mut bitset = Bitmap::<8>::new();
+    /// Switches the given pins to the given mode, releasing pins switched to input mode
+    fn set_modes(&mut self, mask: u8, mode: Mode) -> Result<(), OutputError<B>> {
+        let previous = *self.configuration.as_value();
+
+        self.configuration = match mode {
+            Mode::Input => Bitmap::from_value(previous | mask),
+            Mode::Output => Bitmap::from_value(previous & !mask),
+        };
 
-        if mode == Mode::Input {
-            bitset.invert();
+        if mode == Mode::Output {
+            return Ok(());
         }
 
-        self.configuration = bitset;
-        self.write_conf()
+        if self.committed & mask & OUTPUT_MASK == mask & OUTPUT_MASK {
+            self.output = Bitmap::from_value(self.output_as_value() | mask);
+            return Ok(());
+        }
+
+        self.write_masked(mask, 0xFF).inspect_err(|_| {
+            self.configuration = Bitmap::from_value(previous);
+        })
     }
 
     /// Sets the given output state by adjusting the output register
@@ -210,71 +595,905 @@ where
     /// Note: This just updates the internal register, to make the changes effective,
     /// an additional call to `write_output_state()` is needed.
     pub fn set_state(&mut self, id: PinID, is_high: bool) {
-        self.output.set(id as usize, is_high);
+        self.output.set(id as usize, is_high ^ self.is_active_low(id));
     }
 
     /// Sets output state for all pins
-    pub fn set_state_all(&mut self, is_high: bool) -> Result<(), <B as Write>::Error> {
-        let mut bitset = Bitmap::<8>::new();
-
-        if is_high {
-            bitset.invert();
-        }
+    pub fn set_state_all(&mut self, is_high: bool) -> Result<(), OutputError<B>> {
+        let value = if is_high { 0xFF } else { 0x0 };
 
-        self.output = bitset;
+        self.output = Bitmap::from_value(value ^ self.polarity);
         self.write_output_state()
     }
 
     /// Refreshes the input state
     pub fn refresh_input_state(&mut self) -> Result<(), RefreshInputError<B>> {
-        let mut bitset = Bitmap::from_value(self.read_input_register()?);
-        bitset.invert();
-        self.input = bitset;
-        Ok(())
+        self.flush_overdue();
+
+        let result = self.read_input_register().map(|value| {
+            self.input = Bitmap::from_value(value);
+        });
+
+        if result.is_ok() {
+            self.input_known = true;
+            self.input_refreshed_at = self.now();
+        }
+
+        if let Some(observer) = &self.observer {
+            observer.on_read(self.input_as_value(), result.as_ref().map(|_| ()));
+        }
+
+        if result.is_err() {
+            self.fall_back_on_errors();
+        }
+
+        result
     }
 
     pub fn input_as_value(&self) -> u8 {
         *self.input.as_value()
     }
 
+    /// Returns the cached output register
+    pub fn output_as_value(&self) -> u8 {
+        *self.output.as_value()
+    }
+
+    /// Replaces the cached output register without writing it
+    #[cfg(feature = "portable-atomic")]
+    pub(crate) fn set_output_value(&mut self, value: u8) {
+        self.output = Bitmap::from_value(value);
+    }
+
     /// Returns true if the given pin input is high
     /// Pin needs to be in INPUT mode
     /// This method is using the cached register, for a updated result `refresh_input_state()` needs
     /// to be called beforehand
     pub fn is_pin_input_high(&self, id: PinID) -> bool {
-        self.input.get(id as usize)
+        self.input.get(id as usize) ^ self.is_active_low(id)
     }
 
     /// Returns true if the pins output state is set high
     pub fn is_pin_output_high(&self, id: PinID) -> bool {
-        self.output.get(id as usize)
-   }
+        self.output.get(id as usize) ^ self.is_active_low(id)
+    }
+
+    /// Sets the logical polarity of the given pin
+    /// The output register is not changed, so the logical output state of the pin flips when switching
+    /// the polarity. Hence the polarity is usually set up front, e.g. by [PCA9570::with_config()].
+    pub fn set_polarity(&mut self, id: PinID, polarity: Polarity) {
+        let bit = 1 << id as u8;
+
+        match polarity {
+            Polarity::ActiveHigh => self.polarity &= !bit,
+            Polarity::ActiveLow => self.polarity |= bit,
+        }
+    }
+
+    /// Returns the logical polarity of the given pin
+    pub fn polarity(&self, id: PinID) -> Polarity {
+        match self.is_active_low(id) {
+            true => Polarity::ActiveLow,
+            false => Polarity::ActiveHigh,
+        }
+    }
+
+    /// Returns the inversion mask, set bits are pins with active-low polarity
+    pub fn polarity_mask(&self) -> u8 {
+        self.polarity
+    }
+
+    /// Returns true if the given pin has active-low logical polarity
+    fn is_active_low(&self, id: PinID) -> bool {
+        self.polarity & (1 << id as u8) != 0
+    }
 
     /// Reads and returns the given input register
     fn read_input_register(&mut self) -> Result<u8, RefreshInputError<B>> {
-        self.bus
-            .write(self.address, &[])
-            .map_err(RefreshInputError::WriteError)?;
+        self.write_bus(&[]).map_err(RefreshInputError::WriteError)?;
 
         let mut buffer: [u8; 1] = [0x0; 1];
-        self.bus.read(self.address, &mut buffer).map_err(RefreshInputError::ReadError)?;
+        self.read_bus(&mut buffer).map_err(RefreshInputError::ReadError)?;
 
         Ok(buffer[0])
     }
 
-    /// Writes the configuration register
-    fn write_conf(&mut self) -> Result<(), <B as Write>::Error> {
-        self.bus.write(
+    /// Writes the given bytes to the device
+    fn write_bus(&mut self, bytes: &[u8]) -> Result<(), <B as Write>::Error> {
+        let result = self.bus.as_mut().expect("Bus already taken").write(self.address, bytes);
+        trace!(
+            "PCA9570 {:#x}: write {:?} {}",
             self.address,
-            &[*self.configuration.as_value()],
-        )
+            bytes,
+            Self::status(result.is_ok())
+        );
+
+        #[cfg(feature = "stats")]
+        self.stats.record_write(bytes.len(), result.is_ok(), self.now());
+        self.record_result(result.is_ok());
+
+        if let (Some(observer), Err(error)) = (&self.observer, &result) {
+            observer.on_error(TransactionError::Write(error));
+        }
+
+        result
+    }
+
+    /// Reads from the device into the given buffer
+    fn read_bus(&mut self, buffer: &mut [u8]) -> Result<(), <B as Read>::Error> {
+        let result = self.bus.as_mut().expect("Bus already taken").read(self.address, buffer);
+        trace!(
+            "PCA9570 {:#x}: read {:?} {}",
+            self.address,
+            buffer,
+            Self::status(result.is_ok())
+        );
+
+        #[cfg(feature = "stats")]
+        self.stats.record_read(buffer.len(), result.is_ok(), self.now());
+        self.record_result(result.is_ok());
+
+        if let (Some(observer), Err(error)) = (&self.observer, &result) {
+            observer.on_error(TransactionError::Read(error));
+        }
+
+        result
     }
 
     /// Writes the output register
-    pub fn write_output_state(&mut self) -> Result<(), <B as Write>::Error> {
-        self.bus.write(self.address, &[*self.output.as_value()])
+    /// Failed writes are repeated according to the [retry policy](PCA9570::set_retry_policy).
+    /// Writes violating the interlock rules are rejected without touching the bus, s. [PCA9570::add_interlock()]
+    /// While a [watchdog fault](crate::watchdog) is latched, writes are rejected as well. Transitions
+    /// violating the [toggle limit](PCA9570::set_toggle_limit) are rejected or deferred.
+    pub fn write_output_state(&mut self) -> Result<(), OutputError<B>> {
+        self.write_output(false)
+    }
+
+    /// Writes the output register, writes of the safe state bypass a latched watchdog fault
+    fn write_output(&mut self, is_safe_state: bool) -> Result<(), OutputError<B>> {
+        let mut value = self.output_as_value();
+        let mut deferred = 0x0;
+
+        if !is_safe_state {
+            if let Err(error) = self.check_toggle_limit(value) {
+                return Err(self.reject_output(value, error));
+            }
+
+            deferred = self.deferred_pins(value);
+            value = (value & !deferred) | (self.committed & deferred);
+        }
+
+        if let Err(error) = self.check_output(value, is_safe_state) {
+            return Err(self.reject_output(value, error));
+        }
+
+        let mut result = self.try_write_output(value);
+        let mut retries = 0;
+
+        while result.is_err() && retries < self.retry_policy.retries {
+            retries += 1;
+
+            #[cfg(feature = "stats")]
+            self.stats.record_retry();
+
+            result = self.try_write_output(value);
+        }
+
+        if let Some(observer) = &self.observer {
+            observer.on_write(self.committed, value, result.as_ref().map(|_| ()));
+        }
+
+        self.last_write_failed = result.is_err();
+
+        if result.is_err() {
+            self.fall_back_on_errors();
+            return result;
+        }
+
+        self.record_cycles(value);
+        self.committed = value;
+        self.output_confirmed = true;
+
+        if deferred != 0x0 {
+            trace!("PCA9570 {:#x}: transitions {:#x} deferred", self.address, deferred);
+            self.pending_changes = 1;
+            self.pending_since = self.pending_since.or(self.now());
+            return Ok(());
+        }
+
+        self.pending_changes = 0;
+        self.pending_since = None;
+        Ok(())
     }
 
+    /// Checks the given output register value before writing it
+    /// Writes of the safe state are not checked, so falling back is never blocked.
+    fn check_output(&self, value: u8, is_safe_state: bool) -> Result<(), OutputError<B>> {
+        if is_safe_state {
+            return Ok(());
+        }
+
+        if self.is_watchdog_faulted() {
+            return Err(OutputError::WatchdogFault);
+        }
+
+        self.check_limits(value)
+    }
+
+    /// Checks the given output register value against the interlocks and the current budget
+    fn check_limits(&self, value: u8) -> Result<(), OutputError<B>> {
+        self.check_interlocks(value).map_err(OutputError::Interlock)?;
+
+        if let Some(budget) = self.current_budget {
+            let load = self.load_current_of(value);
+
+            if load > budget {
+                return Err(OutputError::CurrentBudget { load, budget });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Discards the rejected changes, so the cached output register matches the device again
+    fn reject_output(&mut self, value: u8, error: OutputError<B>) -> OutputError<B> {
+        trace!("PCA9570 {:#x}: write {:#x} rejected", self.address, value);
+
+        if let Some(observer) = &self.observer {
+            observer.on_write(self.committed, value, Err(&error));
+        }
+
+        self.output = Bitmap::from_value(self.committed);
+        self.pending_changes = 0;
+        self.pending_since = None;
+        error
+    }
+
+    /// Writes the given value to the output register and reads it back, if verification is enabled
+    fn try_write_output(&mut self, value: u8) -> Result<(), OutputError<B>> {
+        self.write_bus(&[value]).map_err(OutputError::WriteError)?;
+
+        if !self.retry_policy.verify {
+            return Ok(());
+        }
+
+        let actual = self.read_input_register()?;
+        if actual & OUTPUT_MASK != value & OUTPUT_MASK {
+            return Err(OutputError::VerifyMismatch {
+                expected: value,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns a snapshot of the cached output state and pin modes
+    pub fn snapshot(&self) -> OutputSnapshot {
+        let pins = [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3];
+
+        OutputSnapshot {
+            outputs: pins.map(|id| self.is_pin_output_high(id)),
+            modes: pins.map(|id| self.mode(id)),
+        }
+    }
+
+    /// Applies the snapshot using a single write of the output register
+    /// Pin modes are only applied to the cached configuration register, which is not written.
+    pub fn restore(&mut self, snapshot: OutputSnapshot) -> Result<(), OutputError<B>> {
+        for id in [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3] {
+            self.set_state(id, snapshot.outputs[id as usize]);
+            self.configuration.set(id as usize, snapshot.modes[id as usize].into());
+        }
+
+        self.write_output_state()
+    }
+
+    /// Returns a snapshot of the driver state, e.g. for field diagnostics
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            address: self.address,
+            output: self.output_as_value(),
+            device_output: self.output_confirmed.then_some(self.committed),
+            configuration: *self.configuration.as_value(),
+            input: self.input_known.then_some(self.input_as_value()),
+            input_refreshed_at: self.input_refreshed_at,
+            last_write_failed: self.last_write_failed,
+            pending_changes: self.pending_changes,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+        }
+    }
+
+    /// Attaches the observer, replacing any previous one
+    pub fn set_observer<O: Observer<B> + 'static>(&mut self, observer: &'static O) {
+        self.observer = Some(ObserverRef::new(observer));
+    }
+
+    /// Detaches the observer
+    pub fn remove_observer(&mut self) {
+        self.observer = None;
+    }
+
+    /// Sets the policy for writing the output register
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy
+    }
+
+    /// Returns the configured safe state, s. [ExpanderConfig::safe_state()]
+    pub fn safe_state(&self) -> Option<SafeState> {
+        self.safe_state
+    }
+
+    /// Sets the safe state, which is written when the driver or its pins container is dropped, s.
+    /// [PCA9570::apply_safe_state()]
+    ///
+    /// As writing the safe state bypasses the interlocks and the current budget, it is checked against them
+    /// now instead. Pins on hold are checked with their last written value. On error, the previous safe state
+    /// is kept.
+    pub fn set_safe_state(&mut self, safe_state: SafeState) -> Result<(), OutputError<B>> {
+        let previous = self.safe_state.replace(safe_state);
+        let value = self.safe_output_value().unwrap_or(self.committed);
+
+        if let Err(error) = self.check_limits(value) {
+            self.safe_state = previous;
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the safe state, so outputs are kept when dropping the driver
+    pub fn remove_safe_state(&mut self) {
+        self.safe_state = None;
+    }
+
+    /// Writes the safe state, if configured
+    ///
+    /// This is done automatically when the driver or its [Pins] container is dropped, on
+    /// [PCA9570::destroy()] and optionally after consecutive bus errors, s. [SafeState::after_errors()].
+    /// Unwritten changes are discarded. The write is skipped if the device is already in the safe state.
+    pub fn apply_safe_state(&mut self) -> Result<(), OutputError<B>> {
+        match self.safe_output_value() {
+            Some(value) => self.write_fallback(value),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the given fallback value, unless the device is already in this state
+    fn write_fallback(&mut self, value: u8) -> Result<(), OutputError<B>> {
+        trace!("PCA9570 {:#x}: applying safe state {:#x}", self.address, value);
+        self.output = Bitmap::from_value(value);
+
+        if self.output_confirmed && !self.last_write_failed && self.committed == value {
+            self.pending_changes = 0;
+            self.pending_since = None;
+            self.record_skipped_write();
+            return Ok(());
+        }
+
+        self.write_output(true)
+    }
+
+    /// Enables the watchdog mode, s. [watchdog module](crate::watchdog)
+    /// Requires a clock, s. [PCA9570::set_clock()]
+    pub fn enable_watchdog(&mut self, watchdog: Watchdog) {
+        self.watchdog = Some(WatchdogState::new(watchdog, self.now()));
+    }
+
+    /// Disables the watchdog mode, which clears a latched fault as well
+    pub fn disable_watchdog(&mut self) {
+        self.watchdog = None;
+    }
+
+    /// Restarts the watchdog timeout and toggles the heartbeat pin, if configured
+    /// While a fault is latched, the heartbeat pin is not toggled. Cached changes of other pins are not written.
+    pub fn feed(&mut self) -> Result<(), OutputError<B>> {
+        let now = self.now();
+
+        let heartbeat = match &mut self.watchdog {
+            Some(state) => {
+                state.fed_at = now;
+                state.watchdog.heartbeat.filter(|_| !state.latched)
+            }
+            None => return Ok(()),
+        };
+
+        match heartbeat {
+            Some(id) => {
+                // Just the heartbeat pin is written, so other cached changes stay pending
+                let bit = 1 << id as u8;
+                self.write_masked(bit, !self.committed)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the committed output register with the masked bits replaced by the given value
+    /// Other cached changes, e.g. of refreshable or auto-flush pins, are kept pending.
+    fn write_masked(&mut self, mask: u8, value: u8) -> Result<(), OutputError<B>> {
+        let cached = self.output_as_value();
+        let (pending_changes, pending_since) = (self.pending_changes, self.pending_since);
+
+        self.output = Bitmap::from_value((self.committed & !mask) | (value & mask));
+        let result = self.write_output_state();
+
+        self.output = Bitmap::from_value((cached & !mask) | (self.output_as_value() & mask));
+        self.pending_changes = self.pending_changes.max(pending_changes);
+        self.pending_since = pending_since.or(self.pending_since);
+        result
+    }
+
+    /// Checks the watchdog deadline
+    /// If missed, the safe state is written and a fault is latched until calling
+    /// [PCA9570::clear_watchdog_fault()]. Without safe state, all outputs are deasserted, s.
+    /// [PCA9570::emergency_value()]. If writing the safe state fails, each following poll retries.
+    pub fn poll(&mut self) -> Result<WatchdogStatus, OutputError<B>> {
+        let now = self.now();
+
+        let state = match &mut self.watchdog {
+            Some(state) => state,
+            None => return Ok(WatchdogStatus::Disabled),
+        };
+
+        if state.latched && !state.fallback_pending {
+            return Ok(WatchdogStatus::Faulted);
+        }
+
+        if !state.latched {
+            if !state.is_expired(now) {
+                return Ok(WatchdogStatus::Running);
+            }
+
+            // Latching first, so the fault blocks other writes even if writing the safe state fails
+            state.latched = true;
+            state.fallback_pending = true;
+            trace!("PCA9570 {:#x}: watchdog expired", self.address);
+        }
+
+        self.write_fallback(self.emergency_value())?;
+
+        if let Some(state) = &mut self.watchdog {
+            state.fallback_pending = false;
+        }
+
+        Ok(WatchdogStatus::Expired)
+    }
+
+    /// Returns true if a watchdog fault is latched
+    pub fn is_watchdog_faulted(&self) -> bool {
+        self.watchdog.map(|state| state.latched).unwrap_or(false)
+    }
+
+    /// Clears a latched watchdog fault and restarts the timeout
+    pub fn clear_watchdog_fault(&mut self) {
+        let now = self.now();
+
+        if let Some(state) = &mut self.watchdog {
+            state.latched = false;
+            state.fallback_pending = false;
+            state.fed_at = now;
+        }
+    }
+
+    /// Returns the byte written by an [EmergencyStop]
+    /// This is the safe state, or all pins deasserted if no safe state is configured. Pin modes are not taken
+    /// into account, as all pins are in input mode by default.
+    pub fn emergency_value(&self) -> u8 {
+        self.safe_output_value().unwrap_or(self.polarity & OUTPUT_MASK)
+    }
+
+    /// Returns an emergency stop writing the [emergency value](PCA9570::emergency_value) via the given
+    /// separate bus handle, s. [emergency module](crate::emergency)
+    /// The value is captured now, so later changes of the safe state or pins on hold are not reflected.
+    pub fn emergency_stop<E: Write>(&self, bus: E) -> EmergencyStop<E> {
+        EmergencyStop::new(bus, self.address, self.emergency_value())
+    }
+
+    /// Returns the number of failed transactions since the last successful one
+    pub fn consecutive_errors(&self) -> u8 {
+        self.consecutive_errors
+    }
+
+    /// Tracks the series of failed transactions
+    fn record_result(&mut self, is_ok: bool) {
+        if is_ok {
+            self.consecutive_errors = 0;
+            self.fell_back = false;
+        } else {
+            self.consecutive_errors = self.consecutive_errors.saturating_add(1);
+        }
+    }
+
+    /// Writes the safe state once per series of errors, if the configured number of errors is reached
+    fn fall_back_on_errors(&mut self) {
+        let threshold = match self.safe_state.and_then(|state| state.after_errors) {
+            Some(threshold) => threshold,
+            None => return,
+        };
+
+        if self.fell_back || self.consecutive_errors < threshold {
+            return;
+        }
+
+        self.fell_back = true;
+        let _ = self.apply_safe_state();
+    }
+
+    /// Returns the output register value of the safe state, None if no safe state is configured
+    /// Levels of the safe state are logical ones, pins on hold keep the last written value.
+    pub fn safe_output_value(&self) -> Option<u8> {
+        let safe_state = self.safe_state?;
+        let mut value = self.committed;
+
+        for id in [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3] {
+            let is_high = match safe_state.level(id) {
+                SafeLevel::Low => false,
+                SafeLevel::High => true,
+                SafeLevel::Hold => continue,
+            };
+
+            let bit = 1 << id as u8;
+            value = (value & !bit) | if is_high ^ self.is_active_low(id) { bit } else { 0x0 };
+        }
+
+        Some(value)
+    }
+
+    /// Returns the label of the given pin
+    pub fn label(&self, id: PinID) -> Option<&str> {
+        self.labels[id as usize].as_ref().map(Label::as_str)
+    }
+
+    /// Returns the pin with the given label
+    pub fn find_label(&self, label: &str) -> Option<PinID> {
+        [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3]
+            .into_iter()
+            .find(|id| self.label(*id) == Some(label))
+    }
+
+    /// Registers the interlock rule, s. [interlock module](crate::interlock)
+    /// Fails if [MAX_INTERLOCKS] rules are registered already.
+    pub fn add_interlock(&mut self, rule: Interlock) -> Result<(), CapacityError> {
+        let slot = self.interlocks.iter_mut().find(|slot| slot.is_none()).ok_or(CapacityError)?;
+        *slot = Some(rule);
+        Ok(())
+    }
+
+    /// Removes all interlock rules
+    pub fn clear_interlocks(&mut self) {
+        self.interlocks = [None; MAX_INTERLOCKS];
+    }
+
+    /// Returns the registered interlock rules
+    pub fn interlocks(&self) -> impl Iterator<Item = &Interlock> {
+        self.interlocks.iter().flatten()
+    }
+
+    /// Checks the given output register value against the interlock rules
+    /// Returns the first violated rule
+    pub fn check_interlocks(&self, output: u8) -> Result<(), Interlock> {
+        let logical = output ^ self.polarity;
+
+        match self.interlocks().find(|rule| !rule.is_satisfied(logical)) {
+            Some(rule) => Err(*rule),
+            None => Ok(()),
+        }
+    }
+
+    /// Registers the scene, replacing any scene with the same name, s. [scene module](crate::scene)
+    /// Fails if [MAX_SCENES] other scenes are registered already.
+    pub fn add_scene(&mut self, scene: Scene) -> Result<(), CapacityError> {
+        let index = self
+            .scenes
+            .iter()
+            .position(|slot| matches!(slot, Some(existing) if existing.name() == scene.name()))
+            .or_else(|| self.scenes.iter().position(Option::is_none))
+            .ok_or(CapacityError)?;
+
+        self.scenes[index] = Some(scene);
+        Ok(())
+    }
+
+    /// Removes all scenes
+    pub fn clear_scenes(&mut self) {
+        self.scenes = [None; MAX_SCENES];
+    }
+
+    /// Returns the registered scenes
+    pub fn scenes(&self) -> impl Iterator<Item = &Scene> {
+        self.scenes.iter().flatten()
+    }
+
+    /// Returns the scene with the given name
+    pub fn scene(&self, name: &str) -> Option<&Scene> {
+        self.scenes().find(|scene| scene.name() == name)
+    }
+
+    /// Applies the scene with the given name by a single write of the output register
+    /// The write is checked as a whole, e.g. against the interlock rules, before touching the bus. If rejected,
+    /// no pin is changed.
+    pub fn apply_scene(&mut self, name: &str) -> Result<(), SceneError<OutputError<B>>> {
+        let scene = *self.scene(name).ok_or(SceneError::UnknownScene)?;
+        trace!("PCA9570 {:#x}: applying scene {}", self.address, name);
+
+        let logical = self.output_as_value() ^ self.polarity;
+        self.output = Bitmap::from_value(scene.apply_to(logical) ^ self.polarity);
+        self.write_output_state().map_err(SceneError::OutputError)
+    }
+
+    /// Returns the name of the first scene matching the cached output register, None if no scene matches
+    pub fn current_scene(&self) -> Option<&str> {
+        let logical = self.output_as_value() ^ self.polarity;
+
+        self.scenes().find(|scene| scene.matches(logical)).map(Scene::name)
+    }
+
+    /// Declares the load current of the given pin in milliamps, which is drawn while the pin is asserted
+    pub fn set_load_current(&mut self, id: PinID, milliamps: u16) {
+        self.load_currents[id as usize] = milliamps;
+    }
+
+    /// Sets the maximum combined load current in milliamps, s. [current budget](crate::expander#current-budget)
+    pub fn set_current_budget(&mut self, milliamps: u16) {
+        self.current_budget = Some(milliamps);
+    }
+
+    /// Removes the current budget, so writes are not limited anymore
+    pub fn remove_current_budget(&mut self) {
+        self.current_budget = None;
+    }
+
+    /// Returns the current budget in milliamps
+    pub fn current_budget(&self) -> Option<u16> {
+        self.current_budget
+    }
+
+    /// Returns the combined load current in milliamps, based on the last written output state
+    pub fn load_current(&self) -> u16 {
+        self.load_current_of(self.committed)
+    }
+
+    /// Returns the combined load current in milliamps of the given output register value
+    pub fn load_current_of(&self, output: u8) -> u16 {
+        let asserted = (output ^ self.polarity) & OUTPUT_MASK;
+
+        [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3]
+            .into_iter()
+            .filter(|id| asserted & (1 << *id as u8) != 0)
+            .fold(0, |load, id| load.saturating_add(self.load_currents[id as usize]))
+    }
+
+    /// Returns the number of transitions of the given pin
+    pub fn cycle_count(&self, id: PinID) -> u32 {
+        self.cycles[id as usize]
+    }
+
+    /// Returns the switching cycles of all pins, e.g. for persisting them, s. [wear module](crate::wear)
+    pub fn cycle_counters(&self) -> CycleCounters {
+        CycleCounters { cycles: self.cycles }
+    }
+
+    /// Sets the switching cycles of all pins, e.g. restored from persistent storage
+    pub fn set_cycle_counters(&mut self, counters: CycleCounters) {
+        self.cycles = counters.cycles;
+    }
+
+    /// Returns the state to be saved, s. [persist module](crate::persist)
+    #[cfg(feature = "embedded-storage")]
+    pub fn stored_state(&self) -> StoredState {
+        StoredState {
+            output: self.committed,
+            cycles: self.cycle_counters(),
+        }
+    }
+
+    /// Saves the committed output register and the switching cycles, s. [persist module](crate::persist)
+    #[cfg(feature = "embedded-storage")]
+    pub fn save_state<F: NorFlash>(&self, store: &mut OutputStore<F>) -> Result<(), StoreError<F::Error>> {
+        store.save(&self.stored_state())
+    }
+
+    /// Restores the given saved state by a single write of the output register
+    /// The restored switching cycles include the transitions of this write.
+    #[cfg(feature = "embedded-storage")]
+    pub fn restore_stored(&mut self, state: StoredState) -> Result<(), OutputError<B>> {
+        self.set_cycle_counters(state.cycles);
+        self.output = Bitmap::from_value(state.output);
+        self.write_output_state()
+    }
+
+    /// Sets the minimum interval between two transitions of the same pin, s. [wear module](crate::wear)
+    /// Requires a clock, s. [PCA9570::set_clock()]
+    pub fn set_toggle_limit(&mut self, limit: ToggleLimit) {
+        self.toggle_limit = Some(limit);
+    }
+
+    /// Removes the toggle limit
+    pub fn remove_toggle_limit(&mut self) {
+        self.toggle_limit = None;
+    }
+
+    pub fn toggle_limit(&self) -> Option<ToggleLimit> {
+        self.toggle_limit
+    }
+
+    /// Returns the bit mask of pins, which transitions would violate the toggle limit
+    fn too_fast_pins(&self, value: u8) -> u8 {
+        let (min_interval, now) = match (self.toggle_limit, self.now()) {
+            (Some(limit), Some(now)) => (limit.min_interval, now),
+            _ => return 0x0,
+        };
+
+        let changed = (value ^ self.committed) & OUTPUT_MASK;
+
+        [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3]
+            .into_iter()
+            .filter(|id| changed & (1 << *id as u8) != 0)
+            .filter(|id| match self.toggled_at[*id as usize] {
+                Some(toggled_at) => now.saturating_sub(toggled_at) < min_interval,
+                None => false,
+            })
+            .fold(0x0, |mask, id| mask | 1 << id as u8)
+    }
+
+    /// Rejects transitions coming too fast, if configured
+    fn check_toggle_limit(&self, value: u8) -> Result<(), OutputError<B>> {
+        if !matches!(
+            self.toggle_limit,
+            Some(ToggleLimit {
+                action: ToggleAction::Reject,
+                ..
+            })
+        ) {
+            return Ok(());
+        }
+
+        let too_fast = self.too_fast_pins(value);
+        match [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3]
+            .into_iter()
+            .find(|id| too_fast & (1 << *id as u8) != 0)
+        {
+            Some(id) => Err(OutputError::ToggleTooFast(id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the bit mask of pins, which transitions are deferred
+    fn deferred_pins(&self, value: u8) -> u8 {
+        match self.toggle_limit {
+            Some(ToggleLimit {
+                action: ToggleAction::Defer,
+                ..
+            }) => self.too_fast_pins(value),
+            _ => 0x0,
+        }
+    }
+
+    /// Counts the transitions of the written value
+    fn record_cycles(&mut self, value: u8) {
+        let changed = (value ^ self.committed) & OUTPUT_MASK;
+        let now = self.now();
+
+        for id in [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3] {
+            if changed & (1 << id as u8) != 0 {
+                self.cycles[id as usize] = self.cycles[id as usize].saturating_add(1);
+                self.toggled_at[id as usize] = now;
+            }
+        }
+    }
+
+    /// Sets the clock used for time based policies
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = Some(clock);
+    }
+
+    /// Sets the policy for automatically writing changes of pins in auto-flush access mode
+    pub fn set_auto_flush_policy(&mut self, policy: AutoFlushPolicy) {
+        self.auto_flush = policy;
+    }
+
+    /// Returns true if changes of pins in auto-flush access mode are not written yet
+    pub fn has_pending_changes(&self) -> bool {
+        self.pending_changes > 0
+    }
+
+    /// Writes the output register, if changes of pins in auto-flush access mode are pending
+    /// Returns true if the output register was written
+    pub fn flush_pending(&mut self) -> Result<bool, OutputError<B>> {
+        if !self.has_pending_changes() {
+            self.record_skipped_write();
+            return Ok(false);
+        }
+
+        self.write_output_state()?;
+        Ok(true)
+    }
+
+    /// Writes the output register, if pending changes are due according to the auto-flush policy
+    /// Returns true if the output register was written
+    pub fn flush_if_due(&mut self) -> Result<bool, OutputError<B>> {
+        if !self.is_flush_due() {
+            return Ok(false);
+        }
+
+        self.flush_pending()
+    }
+
+    /// Writes pending changes once they are older than the max. age of the auto-flush policy
+    /// Called on each guard access and input refresh. Errors are reported to the observer, the changes stay
+    /// pending and are written by the next call.
+    pub(crate) fn flush_overdue(&mut self) {
+        if self.auto_flush.max_age.is_some() {
+            let _ = self.flush_if_due();
+        }
+    }
+
+    /// Records a change of a pin in auto-flush access mode and writes the output register if due
+    pub(crate) fn record_change(&mut self) -> Result<(), OutputError<B>> {
+        self.pending_changes = self.pending_changes.saturating_add(1);
+
+        if self.pending_since.is_none() {
+            self.pending_since = self.now();
+        }
+
+        self.flush_if_due()?;
+        Ok(())
+    }
+
+    /// Returns true if pending changes are due according to the auto-flush policy
+    fn is_flush_due(&self) -> bool {
+        if !self.has_pending_changes() {
+            return false;
+        }
+
+        if let Some(max_changes) = self.auto_flush.max_changes {
+            if self.pending_changes >= max_changes {
+                return true;
+            }
+        }
+
+        match (self.auto_flush.max_age, self.pending_since, self.now()) {
+            (Some(max_age), Some(since), Some(now)) => now.saturating_sub(since) >= max_age,
+            _ => false,
+        }
+    }
+
+    /// Returns the transaction counters
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Resets all transaction counters
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Records an output write, which was skipped as the device is up to date
+    pub(crate) fn record_skipped_write(&mut self) {
+        #[cfg(feature = "stats")]
+        self.stats.record_skipped_write();
+    }
+
+    /// Transaction status for logging
+    fn status(is_ok: bool) -> &'static str {
+        if is_ok {
+            "OK"
+        } else {
+            "failed"
+        }
+    }
+
+    /// Returns the current timestamp, if a clock is set
+    fn now(&self) -> Option<u64> {
+        self.clock.map(|clock| clock())
+    }
 }
 
 impl From<Mode> for bool {
@@ -295,12 +1514,155 @@ impl<B: Read<u8> + Write> Debug for RefreshInputError<B> {
     }
 }
 
-#[cfg(feature = "alloc")]
-impl<B: Read<u8> + Write> ToString for RefreshInputError<B> {
-    fn to_string(&self) -> String {
+impl<B: Read<u8> + Write> From<RefreshInputError<B>> for OutputError<B> {
+    fn from(error: RefreshInputError<B>) -> Self {
+        match error {
+            RefreshInputError::WriteError(error) => OutputError::WriteError(error),
+            RefreshInputError::ReadError(error) => OutputError::ReadError(error),
+        }
+    }
+}
+
+impl<B: Read<u8> + Write> Debug for OutputError<B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            OutputError::WriteError(_) => f.write_str("OutputError::WriteError"),
+            OutputError::ReadError(_) => f.write_str("OutputError::ReadError"),
+            OutputError::VerifyMismatch { expected, actual } => f
+                .debug_struct("OutputError::VerifyMismatch")
+                .field("expected", &format_args!("{:#010b}", expected))
+                .field("actual", &format_args!("{:#010b}", actual))
+                .finish(),
+            OutputError::Interlock(rule) => f.debug_tuple("OutputError::Interlock").field(rule).finish(),
+            OutputError::WatchdogFault => f.write_str("OutputError::WatchdogFault"),
+            OutputError::CurrentBudget { load, budget } => f
+                .debug_struct("OutputError::CurrentBudget")
+                .field("load", load)
+                .field("budget", budget)
+                .finish(),
+            OutputError::ToggleTooFast(id) => f.debug_tuple("OutputError::ToggleTooFast").field(id).finish(),
+        }
+    }
+}
+
+impl<B> PartialEq for OutputError<B>
+where
+    B: Read<u8> + Write,
+    <B as Write>::Error: PartialEq,
+    <B as Read>::Error: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (OutputError::WriteError(a), OutputError::WriteError(b)) => a == b,
+            (OutputError::ReadError(a), OutputError::ReadError(b)) => a == b,
+            (
+                OutputError::VerifyMismatch { expected, actual },
+                OutputError::VerifyMismatch {
+                    expected: other_expected,
+                    actual: other_actual,
+                },
+            ) => expected == other_expected && actual == other_actual,
+            (OutputError::Interlock(a), OutputError::Interlock(b)) => a == b,
+            (OutputError::WatchdogFault, OutputError::WatchdogFault) => true,
+            (
+                OutputError::CurrentBudget { load, budget },
+                OutputError::CurrentBudget {
+                    load: other_load,
+                    budget: other_budget,
+                },
+            ) => load == other_load && budget == other_budget,
+            (OutputError::ToggleTooFast(a), OutputError::ToggleTooFast(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<B> Drop for PCA9570<B>
+where
+    B: Write<SevenBitAddress> + Read<SevenBitAddress>,
+{
+    /// Writes the safe state, if configured
+    fn drop(&mut self) {
+        if self.bus.is_some() {
+            let _ = self.apply_safe_state();
+        }
+    }
+}
+
+impl<B> Debug for PCA9570<B>
+where
+    B: Write<SevenBitAddress> + Read<SevenBitAddress>,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PCA9570")
+            .field("address", &format_args!("{:#04x}", self.address))
+            .field("output", &format_args!("{:#010b}", self.output_as_value()))
+            .field("committed", &format_args!("{:#010b}", self.committed))
+            .field(
+                "configuration",
+                &format_args!("{:#010b}", self.configuration.as_value()),
+            )
+            .field("polarity", &format_args!("{:#010b}", self.polarity))
+            .field("input", &format_args!("{:#010b}", self.input_as_value()))
+            .field("auto_flush", &self.auto_flush)
+            .field("pending_changes", &self.pending_changes)
+            .field("observer", &self.observer.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<B: Read<u8> + Write> defmt::Format for RefreshInputError<B> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            RefreshInputError::WriteError(_) => defmt::write!(f, "RefreshInputError::WriteError"),
+            RefreshInputError::ReadError(_) => defmt::write!(f, "RefreshInputError::ReadError"),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<B: Read<u8> + Write> defmt::Format for OutputError<B> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            OutputError::WriteError(_) => defmt::write!(f, "OutputError::WriteError"),
+            OutputError::ReadError(_) => defmt::write!(f, "OutputError::ReadError"),
+            OutputError::VerifyMismatch { expected, actual } => {
+                defmt::write!(f, "OutputError::VerifyMismatch({=u8:#x}, {=u8:#x})", expected, actual)
+            }
+            OutputError::Interlock(rule) => defmt::write!(f, "OutputError::Interlock({})", rule),
+            OutputError::WatchdogFault => defmt::write!(f, "OutputError::WatchdogFault"),
+            OutputError::CurrentBudget { load, budget } => {
+                defmt::write!(f, "OutputError::CurrentBudget({=u16} mA > {=u16} mA)", load, budget)
+            }
+            OutputError::ToggleTooFast(id) => defmt::write!(f, "OutputError::ToggleTooFast({})", id),
+        }
+    }
+}
+
+impl<B: Read<u8> + Write> Display for OutputError<B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            OutputError::WriteError(_) => f.write_str("WriteError"),
+            OutputError::ReadError(_) => f.write_str("ReadError"),
+            OutputError::VerifyMismatch { expected, actual } => {
+                write!(f, "VerifyMismatch (expected {:#04x}, read {:#04x})", expected, actual)
+            }
+            OutputError::Interlock(rule) => write!(f, "Interlock ({:?})", rule),
+            OutputError::WatchdogFault => f.write_str("WatchdogFault"),
+            OutputError::CurrentBudget { load, budget } => {
+                write!(f, "CurrentBudget ({} mA exceeds {} mA)", load, budget)
+            }
+            OutputError::ToggleTooFast(id) => write!(f, "ToggleTooFast ({:?})", id),
+        }
+    }
+}
+
+impl<B: Read<u8> + Write> Display for RefreshInputError<B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
-            RefreshInputError::WriteError(_) => "WriteError".to_string(),
-            RefreshInputError::ReadError(_) => "ReadError".to_string(),
+            RefreshInputError::WriteError(_) => f.write_str("WriteError"),
+            RefreshInputError::ReadError(_) => f.write_str("ReadError"),
         }
     }
 }
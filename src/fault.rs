@@ -0,0 +1,363 @@
+//! # Fault injection
+//!
+//! [FaultyBus] wraps any I2C bus and injects faults, e.g. for testing how firmware copes with a
+//! misbehaving expander. Faults are either scheduled for a specific transaction or drawn from a seeded
+//! pseudo random generator, so each test run is reproducible.
+//!
+//! * [Fault::Nack]: The transaction is not acknowledged and does not reach the inner bus
+//! * [Fault::ReadError]: The read transaction fails and does not reach the inner bus
+//! * [Fault::FlipBits]: The read transaction succeeds, but the given bits of the readback are flipped
+//! * [Fault::Stall]: The transaction hangs until it times out and does not reach the inner bus
+//!
+//! Transactions are counted separately for writes and reads, starting with 1.
+//! ```
+//! use pca9570::expander::PinID::Pin0;
+//! use pca9570::expander::{OutputError, PCA9570};
+//! use pca9570::fault::{FaultError, FaultyBus};
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! let simulator = SimulatedPCA9570::default();
+//! let bus = FaultyBus::new(&simulator).nack_write(2).unwrap();
+//! let mut expander = PCA9570::new(bus, 0x24);
+//!
+//! expander.set_state_all(false).unwrap();
+//! expander.set_state(Pin0, true);
+//!
+//! // Second write is not acknowledged, so the device keeps the previous state
+//! assert_eq!(Err(OutputError::WriteError(FaultError::Nack)), expander.write_output_state());
+//! assert_eq!(0b0000_0000, simulator.output());
+//!
+//! // Retrying succeeds
+//! expander.write_output_state().unwrap();
+//! assert_eq!(0b0000_0001, simulator.output());
+//! ```
+//!
+//! ## Random faults
+//! Fault rates are given per mille of transactions.
+//! ```
+//! use pca9570::fault::{FaultRates, FaultyBus};
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! let rates = FaultRates {
+//!     write_nack: 100,
+//!     read_error: 50,
+//!     ..FaultRates::default()
+//! };
+//!
+//! let bus = FaultyBus::new(SimulatedPCA9570::default()).with_random(0xC0FFEE, rates);
+//! ```
+use crate::expander::CapacityError;
+use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
+
+/// Maximum number of scheduled faults per bus
+pub const MAX_SCHEDULED_FAULTS: usize = 16;
+
+/// Injectable faults
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Fault {
+    /// Transaction is not acknowledged
+    Nack,
+
+    /// Read transaction fails
+    ReadError,
+
+    /// Bits of the given mask are flipped in the readback
+    FlipBits(u8),
+
+    /// Transaction stalls until timeout
+    Stall,
+}
+
+/// Type of I2C transaction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Transaction {
+    /// Write transaction, e.g. updating the output register
+    Write,
+
+    /// Read transaction, e.g. refreshing the input state
+    Read,
+}
+
+/// Errors returned by [FaultyBus]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FaultError<E> {
+    /// Injected NACK
+    Nack,
+
+    /// Injected read error
+    ReadError,
+
+    /// Injected stall, the transaction timed out
+    Timeout,
+
+    /// Error of the inner bus
+    BusError(E),
+}
+
+/// Rates of random faults in per mille of transactions
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FaultRates {
+    /// NACK of write transactions
+    pub write_nack: u16,
+
+    /// NACK of read transactions
+    pub read_nack: u16,
+
+    /// Failing read transactions
+    pub read_error: u16,
+
+    /// Single flipped bit in the readback
+    pub bit_flip: u16,
+
+    /// Stalling transactions, both writes and reads
+    pub stall: u16,
+}
+
+/// Fault scheduled for the nth transaction of the given type
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ScheduledFault {
+    transaction: Transaction,
+    nth: usize,
+    fault: Fault,
+}
+
+/// Seeded xorshift generator, good enough for fault injection
+#[derive(Debug, Copy, Clone)]
+struct Random {
+    state: u32,
+}
+
+impl Random {
+    fn new(seed: u32) -> Self {
+        // Xorshift gets stuck on zero
+        Self {
+            state: if seed == 0 { 0x9E37_79B9 } else { seed },
+        }
+    }
+
+    fn next(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    /// Returns true with a probability of the given per mille
+    fn chance(&mut self, per_mille: u16) -> bool {
+        per_mille > 0 && self.next() % 1000 < per_mille as u32
+    }
+}
+
+/// I2C bus wrapper injecting faults
+pub struct FaultyBus<B> {
+    bus: B,
+
+    schedule: [Option<ScheduledFault>; MAX_SCHEDULED_FAULTS],
+
+    random: Option<(Random, FaultRates)>,
+
+    /// Optional callback invoked on each stall, e.g. for advancing a simulated clock
+    on_stall: Option<fn()>,
+
+    /// Number of attempted write transactions
+    writes: usize,
+
+    /// Number of attempted read transactions
+    reads: usize,
+
+    /// Number of injected faults
+    injected: usize,
+}
+
+impl<B> FaultyBus<B> {
+    /// Wraps the given bus without any faults
+    pub fn new(bus: B) -> Self {
+        Self {
+            bus,
+            schedule: [None; MAX_SCHEDULED_FAULTS],
+            random: None,
+            on_stall: None,
+            writes: 0,
+            reads: 0,
+            injected: 0,
+        }
+    }
+
+    /// Schedules the fault for the nth transaction (starting with 1) of the given type.
+    /// If multiple faults are scheduled for the same transaction, the first one wins.
+    /// Read faults scheduled for write transactions are ignored.
+    /// Fails if [MAX_SCHEDULED_FAULTS] faults are scheduled already.
+    pub fn schedule(mut self, transaction: Transaction, nth: usize, fault: Fault) -> Result<Self, CapacityError> {
+        let slot = self.schedule.iter_mut().find(|slot| slot.is_none()).ok_or(CapacityError)?;
+
+        *slot = Some(ScheduledFault {
+            transaction,
+            nth,
+            fault,
+        });
+        Ok(self)
+    }
+
+    /// NACK of the nth write transaction
+    pub fn nack_write(self, nth: usize) -> Result<Self, CapacityError> {
+        self.schedule(Transaction::Write, nth, Fault::Nack)
+    }
+
+    /// NACK of the nth read transaction
+    pub fn nack_read(self, nth: usize) -> Result<Self, CapacityError> {
+        self.schedule(Transaction::Read, nth, Fault::Nack)
+    }
+
+    /// Failure of the nth read transaction
+    pub fn fail_read(self, nth: usize) -> Result<Self, CapacityError> {
+        self.schedule(Transaction::Read, nth, Fault::ReadError)
+    }
+
+    /// Flips the given bits in the readback of the nth read transaction
+    pub fn flip_read_bits(self, nth: usize, mask: u8) -> Result<Self, CapacityError> {
+        self.schedule(Transaction::Read, nth, Fault::FlipBits(mask))
+    }
+
+    /// Stalls the nth write transaction
+    pub fn stall_write(self, nth: usize) -> Result<Self, CapacityError> {
+        self.schedule(Transaction::Write, nth, Fault::Stall)
+    }
+
+    /// Stalls the nth read transaction
+    pub fn stall_read(self, nth: usize) -> Result<Self, CapacityError> {
+        self.schedule(Transaction::Read, nth, Fault::Stall)
+    }
+
+    /// Additionally injects random faults with the given rates.
+    /// Scheduled faults take precedence.
+    pub fn with_random(mut self, seed: u32, rates: FaultRates) -> Self {
+        self.random = Some((Random::new(seed), rates));
+        self
+    }
+
+    /// Sets a callback, which is invoked on each stalled transaction
+    pub fn on_stall(mut self, callback: fn()) -> Self {
+        self.on_stall = Some(callback);
+        self
+    }
+
+    /// Returns the number of attempted write transactions, including faulty ones
+    pub fn write_count(&self) -> usize {
+        self.writes
+    }
+
+    /// Returns the number of attempted read transactions, including faulty ones
+    pub fn read_count(&self) -> usize {
+        self.reads
+    }
+
+    /// Returns the number of injected faults
+    pub fn injected_faults(&self) -> usize {
+        self.injected
+    }
+
+    /// Returns a reference to the inner bus
+    pub fn inner(&self) -> &B {
+        &self.bus
+    }
+
+    /// Returns the inner bus
+    pub fn into_inner(self) -> B {
+        self.bus
+    }
+
+    /// Determines the fault of the current transaction
+    fn next_fault(&mut self, transaction: Transaction) -> Option<Fault> {
+        let nth = match transaction {
+            Transaction::Write => self.writes,
+            Transaction::Read => self.reads,
+        };
+
+        let scheduled = self
+            .schedule
+            .iter()
+            .flatten()
+            .find(|scheduled| scheduled.transaction == transaction && scheduled.nth == nth)
+            .map(|scheduled| scheduled.fault);
+
+        let fault = scheduled
+            .or_else(|| self.random_fault(transaction))
+            // Read faults have no effect on writes
+            .filter(|fault| transaction == Transaction::Read || matches!(fault, Fault::Nack | Fault::Stall));
+
+        if let Some(fault) = fault {
+            self.injected += 1;
+
+            if fault == Fault::Stall {
+                if let Some(callback) = self.on_stall {
+                    callback();
+                }
+            }
+        }
+
+        fault
+    }
+
+    fn random_fault(&mut self, transaction: Transaction) -> Option<Fault> {
+        let (random, rates) = self.random.as_mut()?;
+
+        if random.chance(rates.stall) {
+            return Some(Fault::Stall);
+        }
+
+        match transaction {
+            Transaction::Write => random.chance(rates.write_nack).then_some(Fault::Nack),
+            Transaction::Read => {
+                if random.chance(rates.read_nack) {
+                    Some(Fault::Nack)
+                } else if random.chance(rates.read_error) {
+                    Some(Fault::ReadError)
+                } else if random.chance(rates.bit_flip) {
+                    Some(Fault::FlipBits(1 << (random.next() % 8)))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl<B: Write> Write for FaultyBus<B> {
+    type Error = FaultError<B::Error>;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.writes += 1;
+
+        match self.next_fault(Transaction::Write) {
+            Some(Fault::Nack) => Err(FaultError::Nack),
+            Some(Fault::Stall) => Err(FaultError::Timeout),
+            Some(Fault::ReadError) | Some(Fault::FlipBits(_)) | None => {
+                self.bus.write(address, bytes).map_err(FaultError::BusError)
+            }
+        }
+    }
+}
+
+impl<B: Read> Read for FaultyBus<B> {
+    type Error = FaultError<B::Error>;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.reads += 1;
+
+        match self.next_fault(Transaction::Read) {
+            Some(Fault::Nack) => Err(FaultError::Nack),
+            Some(Fault::ReadError) => Err(FaultError::ReadError),
+            Some(Fault::Stall) => Err(FaultError::Timeout),
+            Some(Fault::FlipBits(mask)) => {
+                self.bus.read(address, buffer).map_err(FaultError::BusError)?;
+                buffer.iter_mut().for_each(|byte| *byte ^= mask);
+                Ok(())
+            }
+            None => self.bus.read(address, buffer).map_err(FaultError::BusError),
+        }
+    }
+}
@@ -0,0 +1,18 @@
+//! Logging macros dispatching to `defmt` and `log`, depending on the enabled features.
+//! Format strings need to be compatible with both, e.g. `{}`, `{:?}` and `{:#x}`.
+#![allow(unused_macros)]
+
+macro_rules! trace {
+    ($($arg:expr),* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::trace!($($arg),*);
+
+            #[cfg(feature = "log")]
+            ::log::trace!($($arg),*);
+
+            #[cfg(not(any(feature = "defmt", feature = "log")))]
+            let _ = ($(&$arg),*);
+        }
+    };
+}
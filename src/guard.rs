@@ -2,19 +2,68 @@
 //!
 //! See [concurrency section](crate::pins#concurrency) for more details.
 
-use crate::expander::PCA9570;
+#[cfg(feature = "portable-atomic")]
+use crate::expander::OutputError;
+use crate::expander::{PinID, PCA9570};
+#[cfg(feature = "portable-atomic")]
+use crate::pins::PinError;
 use core::cell::RefCell;
+#[cfg(feature = "portable-atomic")]
+use core::marker::PhantomData;
 use core::ops::DerefMut;
 use embedded_hal::blocking::i2c::{Read, Write};
+#[cfg(feature = "portable-atomic")]
+use portable_atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Error when accessing the expander through a guard
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AccessError {
+    /// The expander is already borrowed, e.g. by a pin used within another pin's access or by an
+    /// interrupt preempting the main loop
+    Reentrant,
+}
 
 /// Manages the access of pins to expander reference
 pub trait RefGuard<B>
 where
     B: Write + Read<u8>,
 {
-    fn access<F>(&self, f: F)
+    /// Calls the given closure with exclusive access to the expander
+    /// Nested access does not panic, but returns [AccessError::Reentrant] without calling the closure
+    fn access<F>(&self, f: F) -> Result<(), AccessError>
     where
         F: FnMut(&mut PCA9570<B>);
+
+    /// Sets the given output state in the shadow register, without writing it to the bus
+    /// Guards may override this for updating the state without taking a lock
+    fn set_shadow_state(&self, id: PinID, is_high: bool) -> Result<(), AccessError> {
+        self.access(|expander| expander.set_state(id, is_high))
+    }
+}
+
+/// Object safe counterpart of [RefGuard], used by type-erased pins
+pub(crate) trait DynRefGuard<B>
+where
+    B: Write + Read<u8>,
+{
+    fn access_dyn(&self, f: &mut dyn FnMut(&mut PCA9570<B>)) -> Result<(), AccessError>;
+
+    fn set_shadow_state_dyn(&self, id: PinID, is_high: bool) -> Result<(), AccessError>;
+}
+
+impl<B, R> DynRefGuard<B> for R
+where
+    B: Write + Read<u8>,
+    R: RefGuard<B>,
+{
+    fn access_dyn(&self, f: &mut dyn FnMut(&mut PCA9570<B>)) -> Result<(), AccessError> {
+        self.access(f)
+    }
+
+    fn set_shadow_state_dyn(&self, id: PinID, is_high: bool) -> Result<(), AccessError> {
+        self.set_shadow_state(id, is_high)
+    }
 }
 
 /// Guard which is neither Send or Sync, but is the most efficient
@@ -35,11 +84,14 @@ impl<'a, B> RefGuard<B> for LockFreeGuard<'a, B>
 where
     B: Write + Read<u8>,
 {
-    fn access<F>(&self, mut f: F)
+    fn access<F>(&self, mut f: F) -> Result<(), AccessError>
     where
         F: FnMut(&mut PCA9570<B>),
     {
-        f(self.expander.borrow_mut().deref_mut());
+        let mut expander = self.expander.try_borrow_mut().map_err(|_| AccessError::Reentrant)?;
+        f(expander.deref_mut());
+        expander.flush_overdue();
+        Ok(())
     }
 }
 
@@ -67,12 +119,15 @@ impl<'a, B> RefGuard<B> for CsMutexGuard<'a, B>
 where
     B: Write + Read<u8>,
 {
-    fn access<F>(&self, mut f: F)
+    fn access<F>(&self, mut f: F) -> Result<(), AccessError>
     where
         F: FnMut(&mut PCA9570<B>),
     {
         cortex_m::interrupt::free(|cs| {
-            f(self.expander.borrow(cs).borrow_mut().deref_mut());
+            let mut expander = self.expander.borrow(cs).try_borrow_mut().map_err(|_| AccessError::Reentrant)?;
+            f(expander.deref_mut());
+            expander.flush_overdue();
+            Ok(())
         })
     }
 }
@@ -100,10 +155,148 @@ impl<'a, B> RefGuard<B> for SpinGuard<'a, B>
 where
     B: Write + Read<u8>,
 {
-    fn access<F>(&self, mut f: F)
+    fn access<F>(&self, mut f: F) -> Result<(), AccessError>
+    where
+        F: FnMut(&mut PCA9570<B>),
+    {
+        let lock = self.expander.lock();
+        let mut expander = lock.try_borrow_mut().map_err(|_| AccessError::Reentrant)?;
+        f(expander.deref_mut());
+        expander.flush_overdue();
+        Ok(())
+    }
+}
+
+/// Wraps another guard and keeps the shadow output register in an atomic
+///
+/// Setting the output state of refreshable pins is a lock-free bit update, so it can be done from
+/// interrupts without waiting for the lock of the inner guard. Only writing the output register to the
+/// bus is taking the lock, s. [flush_pending()](AtomicShadowGuard::flush_pending).
+///
+/// *Requires activation of `portable-atomic` feature*. On targets without atomic CAS, e.g. thumbv6m,
+/// one of the `critical-section` or `unsafe-assume-single-core` features of `portable-atomic` is needed.
+#[cfg(feature = "portable-atomic")]
+pub struct AtomicShadowGuard<B, R>
+where
+    B: Write + Read<u8>,
+    R: RefGuard<B>,
+{
+    inner: R,
+
+    /// Shadow output register
+    output: AtomicU8,
+
+    /// True if the shadow register was changed since the last flush
+    pending: AtomicBool,
+
+    /// Polarity mask of the expander, updated on each access
+    polarity: AtomicU8,
+
+    bus: PhantomData<fn(B) -> B>,
+}
+
+#[cfg(feature = "portable-atomic")]
+impl<B, R> AtomicShadowGuard<B, R>
+where
+    B: Write + Read<u8>,
+    R: RefGuard<B>,
+{
+    pub fn new(inner: R) -> Self {
+        let mut output = 0x0;
+        let mut polarity = 0x0;
+
+        // A freshly wrapped guard can not be accessed already
+        let _ = inner.access(|expander| {
+            output = expander.output_as_value();
+            polarity = expander.polarity_mask();
+        });
+
+        Self {
+            inner,
+            output: AtomicU8::new(output),
+            pending: AtomicBool::new(false),
+            polarity: AtomicU8::new(polarity),
+            bus: PhantomData,
+        }
+    }
+
+    /// Writes the output register, if the shadow register was changed since the last flush
+    /// Returns true if the output register was written
+    /// In case of an error, the changes remain pending and are written on the next call.
+    pub fn flush_pending(&self) -> Result<bool, PinError<OutputError<B>>> {
+        let mut result = Ok(false);
+
+        self.inner.access(|expander| {
+            // Clearing the flag before loading the shadow register, so lock-free changes made meanwhile stay
+            // pending and are written by the next flush
+            if !self.pending.swap(false, Ordering::AcqRel) {
+                expander.record_skipped_write();
+                return;
+            }
+
+            let shadow = self.output.load(Ordering::Acquire);
+            expander.set_output_value(shadow);
+
+            result = expander.write_output_state().map(|_| true);
+            self.merge_changes(shadow, expander.output_as_value());
+        })?;
+
+        if result.is_err() {
+            self.pending.store(true, Ordering::Release);
+        }
+
+        result.map_err(PinError::BusError)
+    }
+
+    /// Returns true if the shadow register was changed since the last flush
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    /// Applies the bits changed by the expander to the shadow register
+    /// Just the changed bits are applied, as other bits may have been set lock-free meanwhile.
+    fn merge_changes(&self, shadow: u8, output: u8) {
+        let changed = shadow ^ output;
+
+        if changed != 0 {
+            let _ = self.output.fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                Some((current & !changed) | (output & changed))
+            });
+        }
+    }
+}
+
+#[cfg(feature = "portable-atomic")]
+impl<B, R> RefGuard<B> for AtomicShadowGuard<B, R>
+where
+    B: Write + Read<u8>,
+    R: RefGuard<B>,
+{
+    fn access<F>(&self, mut f: F) -> Result<(), AccessError>
     where
         F: FnMut(&mut PCA9570<B>),
     {
-        f(self.expander.lock().borrow_mut().deref_mut());
+        self.inner.access(|expander| {
+            let shadow = self.output.load(Ordering::Acquire);
+            expander.set_output_value(shadow);
+
+            f(expander);
+            self.polarity.store(expander.polarity_mask(), Ordering::Release);
+            self.merge_changes(shadow, expander.output_as_value());
+        })
+    }
+
+    fn set_shadow_state(&self, id: PinID, is_high: bool) -> Result<(), AccessError> {
+        let bit = 1 << id as u8;
+        let is_active_low = self.polarity.load(Ordering::Acquire) & bit != 0;
+
+        if is_high ^ is_active_low {
+            self.output.fetch_or(bit, Ordering::AcqRel);
+        } else {
+            self.output.fetch_and(!bit, Ordering::AcqRel);
+        }
+
+        self.pending.store(true, Ordering::Release);
+        Ok(())
     }
 }
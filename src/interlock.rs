@@ -0,0 +1,73 @@
+//! # Interlocks
+//!
+//! Interlock rules prevent dangerous output combinations, e.g. forward and reverse contactors being
+//! asserted together. Rules are registered by [PCA9570::add_interlock()](crate::expander::PCA9570::add_interlock)
+//! and checked before each write of the output register, i.e. for central I/O control as well as for
+//! individual pins of any access mode.
+//!
+//! Writes violating a rule fail with [OutputError::Interlock](crate::expander::OutputError::Interlock)
+//! without touching the bus. The rejected changes are discarded, so the cached output register matches the
+//! device again. Rules are checked against logical states, s. [polarity](crate::expander#polarity).
+//! ```
+//! use pca9570::expander::PinID::{Pin0, Pin1, Pin2, Pin3};
+//! use pca9570::expander::{OutputError, PCA9570};
+//! use pca9570::interlock::Interlock;
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+//! expander.set_state_all(false).unwrap();
+//!
+//! // Forward and reverse contactor
+//! expander.add_interlock(Interlock::exclusive(&[Pin0], &[Pin1])).unwrap();
+//! // Pump requires the valve to be open
+//! expander.add_interlock(Interlock::requires(&[Pin2], &[Pin3])).unwrap();
+//!
+//! expander.set_state(Pin0, true);
+//! expander.set_state(Pin1, true);
+//! assert_eq!(
+//!     Err(OutputError::Interlock(Interlock::exclusive(&[Pin0], &[Pin1]))),
+//!     expander.write_output_state()
+//! );
+//! assert!(!expander.is_pin_output_high(Pin0));
+//! ```
+use crate::expander::PinID;
+
+/// Maximum number of interlock rules per expander
+pub const MAX_INTERLOCKS: usize = 8;
+
+/// Interlock rule, pin sets are given as bit masks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Interlock {
+    /// Pins of both sets must not be asserted at the same time
+    Exclusive(u8, u8),
+
+    /// If any pin of the first set is asserted, all pins of the second set need to be asserted
+    Requires(u8, u8),
+}
+
+impl Interlock {
+    /// Pins of the first set must not be asserted together with pins of the second set
+    pub fn exclusive(first: &[PinID], second: &[PinID]) -> Self {
+        Interlock::Exclusive(mask(first), mask(second))
+    }
+
+    /// Pins of the first set may just be asserted, if all pins of the second set are asserted
+    pub fn requires(pins: &[PinID], required: &[PinID]) -> Self {
+        Interlock::Requires(mask(pins), mask(required))
+    }
+
+    /// Returns true if the given logical output state satisfies the rule
+    pub fn is_satisfied(&self, output: u8) -> bool {
+        match *self {
+            Interlock::Exclusive(first, second) => output & first == 0 || output & second == 0,
+            Interlock::Requires(pins, required) => output & pins == 0 || output & required == required,
+        }
+    }
+}
+
+/// Returns the bit mask of the given pins
+pub(crate) fn mask(pins: &[PinID]) -> u8 {
+    pins.iter().fold(0x0, |mask, id| mask | 1 << *id as u8)
+}
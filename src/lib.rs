@@ -1,43 +1,91 @@
 //! # Abstraction of PCA9570
 //!
-//! Abstraction for I/O expander [PCA9570]
+//! Abstraction for I/O expander [PCA9570](crate::expander::PCA9570)
 //! This crate offers the following features:
 //! * Individual pin instances, fully implementing [digital::v2 traits of embedded_hal](https://docs.rs/embedded-hal/latest/embedded_hal/digital/v2/index.html)
 //! * Central I/O control, s. [PCA9570 module](crate::expander)
-//! * Two state management modes for reduced I2C overhead, s. [pins module](crate::pins)
+//! * Three state management modes for reduced I2C overhead, s. [pins module](crate::pins)
 //! * Three concurrency models, s. [concurrency section](crate::pins#concurrency)
+//! * Lock-free output updates, e.g. from interrupts, s. [atomic shadow register](crate::pins#atomic-shadow-register)
+//! * Declarative setup with polarity, safe state, retry policy and pin labels, s. [config module](crate::config)
+//! * Safe state written on drop and after consecutive bus errors, s. [safe state section](crate::expander#safe-state)
+//! * Lock-free emergency stop usable from panic handlers, s. [emergency module](crate::emergency)
+//! * Watchdog mode falling back to the safe state if the application stops feeding, s. [watchdog module](crate::watchdog)
+//! * Load current budget rejecting writes above the package limit, s. [current budget section](crate::expander#current-budget)
+//! * Relay wear management by switching-cycle counters and a minimum toggle interval, s. [wear module](crate::wear)
+//! * Persistence of output state and switching cycles in NOR flash, s. `persist` module (feature `embedded-storage`)
+//! * Named scenes applied atomically, s. [scene module](crate::scene)
+//! * Interlock rules rejecting dangerous output combinations, s. [interlock module](crate::interlock)
+//! * Diagnostic snapshot of the driver state, s. [diagnostics module](crate::diagnostics)
+//! * Observer hooks for logging and safety monitors, s. [observer module](crate::observer)
+//! * Behavioral simulation of the device for examples and host based tests, s. [sim module](crate::sim)
+//! * Fault injection for robustness tests, s. [fault module](crate::fault)
+//! * Transaction statistics for field telemetry, s. `stats` module (feature `stats`)
+//! * Recording and replay of I2C traffic, e.g. for golden-trace regression tests, s. [trace module](crate::trace)
+//! * Export of the output pin history as VCD file, s. [vcd module](crate::vcd)
+//! * Expectation bus for unit-testing firmware without I2C mocks, s. `testing` module (feature `testing`)
+//! * Optional `defmt` and `log` support, tracing each bus transaction
+//! * Optional `serde` support for saving and restoring output snapshots and configs, s. [PCA9570::snapshot()](crate::expander::PCA9570::snapshot)
 //! * no_std support
 //!
 //! ## Example
 //! ```
-//! use pca9570::example::DummyI2CBus;
-//! use pca9570::expander::Bank::Bank0;
+//! use pca9570::sim::SimulatedPCA9570;
 //! use pca9570::expander::PCA9570;
 //! use pca9570::expander::PinID::Pin1;
 //! use embedded_hal::digital::v2::InputPin;
 //!
-//! let i2c_bus = DummyI2CBus::default();
+//! let i2c_bus = SimulatedPCA9570::default();
 //! let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //! let pins = expander.pins();
 //!
-//! let pin01 = pins.get_pin(Bank0, Pin1);
+//! let pin01 = pins.get_pin(Pin1);
 //! assert!(pin01.is_high().unwrap());
+//! ```
 #![cfg_attr(not(test), no_std)]
 #![cfg_attr(feature = "strict", deny(warnings))]
 #[cfg(feature = "alloc")]
 extern crate alloc;
 extern crate embedded_hal;
 
+#[macro_use]
+mod fmt;
+
+pub mod config;
+pub mod diagnostics;
+pub mod emergency;
 #[cfg(feature = "example")]
+#[deprecated(note = "use the sim module instead")]
 pub mod example;
 pub mod expander;
+pub mod fault;
 pub mod guard;
+pub mod interlock;
+pub mod observer;
+#[cfg(feature = "embedded-storage")]
+pub mod persist;
 pub mod pins;
+pub mod scene;
+#[cfg(feature = "sim")]
+pub mod sim;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "alloc")]
+pub mod trace;
+#[cfg(feature = "alloc")]
+pub mod vcd;
+pub mod watchdog;
+pub mod wear;
 
+pub(crate) mod pin_auto_flush;
+pub(crate) mod pin_dyn;
+pub(crate) mod pin_erased;
 pub(crate) mod pin_refreshable;
 pub(crate) mod pin_regular;
 
 #[cfg(test)]
 mod mocks;
-#[cfg(test)]
+#[cfg(all(test, feature = "sim"))]
 mod tests;
@@ -68,10 +68,12 @@ impl BusMockBuilder {
         self
     }
 
-    pub fn write_error(mut self, command: u8) -> Self {
+    pub fn write_error(mut self, data: &[u8]) -> Self {
+        let data_vec = data.to_vec();
+
         self.bus.expect_write().times(1).returning(move |address, buffer| {
             assert_eq!(0x24, address);
-            assert_eq!(command, buffer[0]);
+            assert_eq!(data_vec.as_slice(), buffer);
             Err(WriteError::Error1)
         });
 
@@ -0,0 +1,193 @@
+//! # Observer hooks
+//!
+//! An [Observer] is notified about each write of the output register, each refresh of the input register and
+//! each failed I2C transaction, e.g. for logging or safety monitors. The hooks fire on all paths, i.e. for
+//! central I/O control as well as for individual pins of any access mode.
+//!
+//! The observer is attached by `'static` reference, so no boxing is needed on no_std. State of the observer
+//! requires interior mutability, e.g. atomics.
+//! ```
+//! use core::sync::atomic::{AtomicU8, Ordering};
+//! use embedded_hal::blocking::i2c::{Read, Write};
+//! use pca9570::expander::{OutputError, PCA9570};
+//! use pca9570::observer::Observer;
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! struct LastOutput(AtomicU8);
+//!
+//! impl<B: Write + Read> Observer<B> for LastOutput {
+//!     fn on_write(&self, _old: u8, new: u8, result: Result<(), &OutputError<B>>) {
+//!         if result.is_ok() {
+//!             self.0.store(new, Ordering::Relaxed);
+//!         }
+//!     }
+//! }
+//!
+//! static MONITOR: LastOutput = LastOutput(AtomicU8::new(0));
+//!
+//! let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+//! expander.set_observer(&MONITOR);
+//!
+//! expander.set_state_all(false).unwrap();
+//! assert_eq!(0b0000_0000, MONITOR.0.load(Ordering::Relaxed));
+//! ```
+//!
+//! The expander holds a single observer. More observers are attached by [Fanout], which forwards each hook
+//! to two observers and can be nested.
+//! ```
+//! # use core::sync::atomic::{AtomicU8, Ordering};
+//! # use embedded_hal::blocking::i2c::{Read, Write};
+//! # use pca9570::expander::{OutputError, PCA9570};
+//! # use pca9570::observer::{Fanout, Observer};
+//! # use pca9570::sim::SimulatedPCA9570;
+//! #
+//! # struct LastOutput(AtomicU8);
+//! #
+//! # impl<B: Write + Read> Observer<B> for LastOutput {
+//! #     fn on_write(&self, _old: u8, new: u8, result: Result<(), &OutputError<B>>) {
+//! #         if result.is_ok() {
+//! #             self.0.store(new, Ordering::Relaxed);
+//! #         }
+//! #     }
+//! # }
+//! static MONITOR: LastOutput = LastOutput(AtomicU8::new(0));
+//! static LOGGER: LastOutput = LastOutput(AtomicU8::new(0));
+//! static BOTH: Fanout<LastOutput, LastOutput> = Fanout::new(&MONITOR, &LOGGER);
+//!
+//! let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+//! expander.set_observer(&BOTH);
+//!
+//! expander.set_state_all(false).unwrap();
+//! assert_eq!(0b0000_0000, MONITOR.0.load(Ordering::Relaxed));
+//! assert_eq!(0b0000_0000, LOGGER.0.load(Ordering::Relaxed));
+//! ```
+use crate::expander::{OutputError, RefreshInputError};
+use core::any::Any;
+use core::fmt::{Debug, Formatter};
+use embedded_hal::blocking::i2c::{Read, Write};
+
+/// Failed I2C transaction
+pub enum TransactionError<'a, B: Write + Read> {
+    /// Write transaction failed
+    Write(&'a <B as Write>::Error),
+
+    /// Read transaction failed
+    Read(&'a <B as Read>::Error),
+}
+
+impl<B: Write + Read> Clone for TransactionError<'_, B> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<B: Write + Read> Copy for TransactionError<'_, B> {}
+
+impl<B: Write + Read> Debug for TransactionError<'_, B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TransactionError::Write(_) => f.write_str("TransactionError::Write"),
+            TransactionError::Read(_) => f.write_str("TransactionError::Read"),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<B: Write + Read> defmt::Format for TransactionError<'_, B> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            TransactionError::Write(_) => defmt::write!(f, "TransactionError::Write"),
+            TransactionError::Read(_) => defmt::write!(f, "TransactionError::Read"),
+        }
+    }
+}
+
+/// Hooks notified by [PCA9570](crate::expander::PCA9570). All hooks are optional.
+pub trait Observer<B: Write + Read> {
+    /// Called after each write of the output register, including writes rejected by interlock rules.
+    /// Old is the value of the last successful write, or the power-on cache before. The result is the one
+    /// of the last attempt, if writes are repeated according to the retry policy.
+    fn on_write(&self, _old: u8, _new: u8, _result: Result<(), &OutputError<B>>) {}
+
+    /// Called after each refresh of the input register.
+    /// On failure the value is the unchanged input cache.
+    fn on_read(&self, _value: u8, _result: Result<(), &RefreshInputError<B>>) {}
+
+    /// Called after each failed I2C transaction
+    fn on_error(&self, _error: TransactionError<'_, B>) {}
+}
+
+/// Observer forwarding each hook to both observers, first to the left one
+pub struct Fanout<L: 'static, R: 'static> {
+    left: &'static L,
+
+    right: &'static R,
+}
+
+impl<L, R> Fanout<L, R> {
+    /// Creates the fan-out, notifying the left observer first
+    pub const fn new(left: &'static L, right: &'static R) -> Self {
+        Self { left, right }
+    }
+}
+
+impl<B: Write + Read, L: Observer<B>, R: Observer<B>> Observer<B> for Fanout<L, R> {
+    fn on_write(&self, old: u8, new: u8, result: Result<(), &OutputError<B>>) {
+        self.left.on_write(old, new, result);
+        self.right.on_write(old, new, result);
+    }
+
+    fn on_read(&self, value: u8, result: Result<(), &RefreshInputError<B>>) {
+        self.left.on_read(value, result);
+        self.right.on_read(value, result);
+    }
+
+    fn on_error(&self, error: TransactionError<'_, B>) {
+        self.left.on_error(error);
+        self.right.on_error(error);
+    }
+}
+
+type WriteHook<B> = fn(&'static dyn Any, u8, u8, Result<(), &OutputError<B>>);
+
+/// Type erased reference to an observer
+///
+/// Storing `&'static dyn Observer<B>` would require `B: 'static`, which excludes buses passed by reference.
+/// So the observer is stored as [Any] together with monomorphized hooks casting it back.
+pub(crate) struct ObserverRef<B: Write + Read> {
+    observer: &'static dyn Any,
+
+    on_write: WriteHook<B>,
+
+    on_read: fn(&'static dyn Any, u8, Result<(), &RefreshInputError<B>>),
+
+    on_error: fn(&'static dyn Any, TransactionError<'_, B>),
+}
+
+impl<B: Write + Read> ObserverRef<B> {
+    pub(crate) fn new<O: Observer<B> + 'static>(observer: &'static O) -> Self {
+        Self {
+            observer,
+            on_write: |observer, old, new, result| Self::cast::<O>(observer).on_write(old, new, result),
+            on_read: |observer, value, result| Self::cast::<O>(observer).on_read(value, result),
+            on_error: |observer, error| Self::cast::<O>(observer).on_error(error),
+        }
+    }
+
+    pub(crate) fn on_write(&self, old: u8, new: u8, result: Result<(), &OutputError<B>>) {
+        (self.on_write)(self.observer, old, new, result)
+    }
+
+    pub(crate) fn on_read(&self, value: u8, result: Result<(), &RefreshInputError<B>>) {
+        (self.on_read)(self.observer, value, result)
+    }
+
+    pub(crate) fn on_error(&self, error: TransactionError<'_, B>) {
+        (self.on_error)(self.observer, error)
+    }
+
+    fn cast<O: 'static>(observer: &'static dyn Any) -> &'static O {
+        // Hooks are only created together with the observer of the same type
+        observer.downcast_ref::<O>().expect("Observer type mismatch")
+    }
+}
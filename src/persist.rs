@@ -0,0 +1,252 @@
+//! # Persistence
+//!
+//! Saves the committed output register and the [switching cycles](crate::wear) to NOR flash via the
+//! [embedded-storage](https://docs.rs/embedded-storage/latest/embedded_storage/nor_flash/index.html) traits,
+//! so outputs return to their last commanded state after a reboot.
+//!
+//! The state is saved explicitly by [PCA9570::save_state()](crate::expander::PCA9570::save_state), e.g. after
+//! changing outputs, as each save erases the flash region. On startup,
+//! [PCA9570::with_store()](crate::expander::PCA9570::with_store) restores the saved state by a single write of
+//! the output register. Pin modes and polarity are not saved, they are part of the
+//! [configuration](crate::config). With erased flash, the driver starts as usual. A corrupt record, e.g. torn by
+//! a power loss between erasing and writing, is reported, but the driver starts as usual as well. When created by
+//! [PCA9570::with_config()](crate::expander::PCA9570::with_config), the loaded state is applied by
+//! [PCA9570::restore_stored()](crate::expander::PCA9570::restore_stored) instead.
+//! ```
+//! use pca9570::expander::PinID::{Pin0, Pin1};
+//! use pca9570::expander::PCA9570;
+//! use pca9570::persist::OutputStore;
+//! use pca9570::sim::{SimulatedFlash, SimulatedPCA9570};
+//!
+//! let simulator = SimulatedPCA9570::default();
+//! let mut store = OutputStore::new(SimulatedFlash::<256>::default(), 0);
+//!
+//! let (mut expander, result) = PCA9570::with_store(&simulator, 0x24, &mut store);
+//! result.unwrap();
+//! expander.set_state(Pin0, false);
+//! expander.set_state(Pin1, false);
+//! expander.write_output_state().unwrap();
+//! expander.save_state(&mut store).unwrap();
+//!
+//! // After reboot
+//! simulator.power_cycle();
+//! assert_eq!([true, true, true, true], simulator.pins());
+//!
+//! let (expander, result) = PCA9570::with_store(&simulator, 0x24, &mut store);
+//! result.unwrap();
+//! assert_eq!([false, false, true, true], simulator.pins());
+//! ```
+//! ## Layout
+//! The record is stored at the given offset, which needs to be aligned to the erase size of the flash.
+//! All numbers are little endian.
+//!
+//! | Bytes  | Content                                  |
+//! |--------|------------------------------------------|
+//! | 0..2   | Magic number `0x95 0x70`                 |
+//! | 2      | Layout version, currently 1              |
+//! | 3      | Output register                          |
+//! | 4..20  | Switching cycles of Pin0 to Pin3, `u32`  |
+//! | 20..24 | CRC-32 (IEEE) of bytes 0..20             |
+use crate::wear::CycleCounters;
+use core::convert::Infallible;
+use core::fmt::{Debug, Display, Formatter};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Current layout version
+pub const LAYOUT_VERSION: u8 = 1;
+
+/// Length of a record in bytes, s. [layout](crate::persist#layout)
+pub const RECORD_LEN: usize = 24;
+
+/// Maximum length of a record padded to the read or write size of the flash
+const BUFFER_LEN: usize = 64;
+
+/// Identifies a saved record
+const MAGIC: [u8; 2] = [0x95, 0x70];
+
+/// Saved state of the driver
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StoredState {
+    /// Committed output register
+    pub output: u8,
+
+    /// Switching cycles of all pins
+    pub cycles: CycleCounters,
+}
+
+/// Record of the driver state in NOR flash, s. [module](crate::persist)
+#[derive(Debug)]
+pub struct OutputStore<F> {
+    flash: F,
+
+    /// Start of the record, aligned to the erase size
+    offset: u32,
+}
+
+impl<F: ReadNorFlash> OutputStore<F> {
+    /// Creates the store, the record is placed at the given offset
+    pub fn new(flash: F, offset: u32) -> Self {
+        Self { flash, offset }
+    }
+
+    /// Loads the saved state, None if no state was saved yet
+    pub fn load(&mut self) -> Result<Option<StoredState>, StoreError<F::Error>> {
+        let mut buffer = [0x0; BUFFER_LEN];
+        let length = padded_len(RECORD_LEN, F::READ_SIZE)?;
+
+        self.flash.read(self.offset, &mut buffer[..length]).map_err(StoreError::Flash)?;
+        decode(&buffer[..RECORD_LEN])
+    }
+
+    /// Returns the flash
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+}
+
+impl<F: NorFlash> OutputStore<F> {
+    /// Erases the flash region and writes the given state
+    pub fn save(&mut self, state: &StoredState) -> Result<(), StoreError<F::Error>> {
+        let mut buffer = [0xFF; BUFFER_LEN];
+        buffer[..RECORD_LEN].copy_from_slice(&encode(state));
+
+        let length = padded_len(RECORD_LEN, F::WRITE_SIZE)?;
+        let erase_len = length.div_ceil(F::ERASE_SIZE) * F::ERASE_SIZE;
+
+        self.flash
+            .erase(self.offset, self.offset + erase_len as u32)
+            .map_err(StoreError::Flash)?;
+        self.flash.write(self.offset, &buffer[..length]).map_err(StoreError::Flash)
+    }
+
+    /// Erases the flash region, so the driver starts as usual next time
+    pub fn clear(&mut self) -> Result<(), StoreError<F::Error>> {
+        let length = padded_len(RECORD_LEN, F::WRITE_SIZE)?;
+        let erase_len = length.div_ceil(F::ERASE_SIZE) * F::ERASE_SIZE;
+
+        self.flash
+            .erase(self.offset, self.offset + erase_len as u32)
+            .map_err(StoreError::Flash)
+    }
+}
+
+/// Error of loading or saving the driver state
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StoreError<F, E = Infallible> {
+    /// Flash access failed
+    Flash(F),
+
+    /// Record has an invalid magic number or checksum
+    Corrupt,
+
+    /// Record was saved using an unknown layout version
+    UnsupportedVersion(u8),
+
+    /// Read or write size of the flash exceeds the supported 64 bytes
+    UnsupportedFlash,
+
+    /// Writing the restored output state failed
+    BusError(E),
+}
+
+impl<F> StoreError<F> {
+    /// Converts a flash error
+    pub(crate) fn widen<E>(self) -> StoreError<F, E> {
+        match self {
+            StoreError::Flash(error) => StoreError::Flash(error),
+            StoreError::Corrupt => StoreError::Corrupt,
+            StoreError::UnsupportedVersion(version) => StoreError::UnsupportedVersion(version),
+            StoreError::UnsupportedFlash => StoreError::UnsupportedFlash,
+            StoreError::BusError(error) => match error {},
+        }
+    }
+}
+
+impl<F: Debug, E: Display> Display for StoreError<F, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StoreError::Flash(error) => write!(f, "Flash({:?})", error),
+            StoreError::Corrupt => f.write_str("Corrupt"),
+            StoreError::UnsupportedVersion(version) => write!(f, "UnsupportedVersion({})", version),
+            StoreError::UnsupportedFlash => f.write_str("UnsupportedFlash"),
+            StoreError::BusError(error) => error.fmt(f),
+        }
+    }
+}
+
+/// Returns the given length rounded up to the alignment
+fn padded_len<F>(length: usize, align: usize) -> Result<usize, StoreError<F>> {
+    let padded = length.div_ceil(align) * align;
+
+    match padded <= BUFFER_LEN {
+        true => Ok(padded),
+        false => Err(StoreError::UnsupportedFlash),
+    }
+}
+
+/// Serializes the state using the current layout
+fn encode(state: &StoredState) -> [u8; RECORD_LEN] {
+    let mut record = [0x0; RECORD_LEN];
+    record[..2].copy_from_slice(&MAGIC);
+    record[2] = LAYOUT_VERSION;
+    record[3] = state.output;
+
+    for (index, cycles) in state.cycles.cycles.iter().enumerate() {
+        record[4 + index * 4..8 + index * 4].copy_from_slice(&cycles.to_le_bytes());
+    }
+
+    let crc = crc32(&record[..20]);
+    record[20..].copy_from_slice(&crc.to_le_bytes());
+    record
+}
+
+/// Deserializes the given record, None if erased
+fn decode<F>(record: &[u8]) -> Result<Option<StoredState>, StoreError<F>> {
+    if record.iter().all(|byte| *byte == 0xFF) {
+        return Ok(None);
+    }
+
+    if record[..2] != MAGIC {
+        return Err(StoreError::Corrupt);
+    }
+
+    if record[2] != LAYOUT_VERSION {
+        return Err(StoreError::UnsupportedVersion(record[2]));
+    }
+
+    if crc32(&record[..20]).to_le_bytes() != record[20..24] {
+        return Err(StoreError::Corrupt);
+    }
+
+    let mut cycles = [0; 4];
+    for (index, count) in cycles.iter_mut().enumerate() {
+        let bytes = &record[4 + index * 4..8 + index * 4];
+        *count = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    Ok(Some(StoredState {
+        output: record[3],
+        cycles: CycleCounters { cycles },
+    }))
+}
+
+/// CRC-32 (IEEE 802.3), computed bitwise to avoid a lookup table
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
@@ -0,0 +1,179 @@
+use crate::expander::{Mode, OutputError, PinID, RefreshInputError};
+use crate::guard::RefGuard;
+use crate::pin_refreshable::RefreshableOutputPin;
+use crate::pins::{AutoFlushMode, Input, Output, Pin, PinError, PinMode};
+use core::marker::PhantomData;
+use embedded_hal::blocking::i2c::{Read, Write};
+use embedded_hal::digital::v2::{toggleable, InputPin, IoPin, OutputPin, PinState, StatefulOutputPin};
+
+/// Writes pending changes of pins in [auto-flush access mode](AutoFlushMode) when dropped
+pub struct FlushScope<'a, B, R>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    pub(crate) expander: &'a R,
+    pub(crate) bus: PhantomData<fn(B) -> B>,
+}
+
+impl<'a, B, R> Drop for FlushScope<'a, B, R>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    /// Errors are ignored, the changes remain pending in this case
+    fn drop(&mut self) {
+        let _ = self.expander.access(|expander| {
+            let _ = expander.flush_pending();
+        });
+    }
+}
+
+impl<'a, B, R> Pin<'a, B, R, Input, AutoFlushMode>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    pub fn auto_flush(expander: &'a R, id: PinID) -> Self {
+        Self {
+            expander,
+            bus: PhantomData,
+            id,
+            access_mode: PhantomData,
+            mode: PhantomData,
+        }
+    }
+}
+
+impl<'a, B, R> InputPin for Pin<'a, B, R, Input, AutoFlushMode>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    type Error = PinError<RefreshInputError<B>>;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        let mut result = Ok(false);
+
+        self.expander.access(|expander| {
+            result = match expander.refresh_input_state() {
+                Ok(_) => Ok(expander.is_pin_input_high(self.id)),
+                Err(error) => Err(error),
+            }
+        })?;
+
+        result.map_err(PinError::BusError)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl<'a, B, R> RefreshableOutputPin for Pin<'a, B, R, Output, AutoFlushMode>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    type Error = PinError<OutputError<B>>;
+
+    /// Writes the output state of all pins, regardless of the auto-flush policy
+    fn update_all(&self) -> Result<(), Self::Error> {
+        let mut result = Ok(());
+
+        self.expander.access(|expander| {
+            result = expander.write_output_state();
+        })?;
+
+        result.map_err(PinError::BusError)
+    }
+}
+
+impl<'a, B, R> OutputPin for Pin<'a, B, R, Output, AutoFlushMode>
+where
+    B: Read + Write,
+    R: RefGuard<B>,
+{
+    type Error = PinError<OutputError<B>>;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::Low)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::High)
+    }
+
+    /// Records the change, the output register is written once due according to the auto-flush policy
+    fn set_state(&mut self, state: PinState) -> Result<(), Self::Error> {
+        let mut result = Ok(());
+
+        self.expander.access(|expander| {
+            expander.set_state(self.id, state == PinState::High);
+            result = expander.record_change();
+        })?;
+
+        result.map_err(PinError::BusError)
+    }
+}
+
+impl<'a, B, R> StatefulOutputPin for Pin<'a, B, R, Output, AutoFlushMode>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.is_pin_output_high()?)
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_pin_output_high()?)
+    }
+}
+
+impl<'a, B, R> toggleable::Default for Pin<'a, B, R, Output, AutoFlushMode>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+}
+
+impl<'a, B, M, R> IoPin<Pin<'a, B, R, Input, AutoFlushMode>, Pin<'a, B, R, Output, AutoFlushMode>>
+    for Pin<'a, B, R, M, AutoFlushMode>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+    M: PinMode,
+{
+    type Error = PinError<OutputError<B>>;
+
+    fn into_input_pin(self) -> Result<Pin<'a, B, R, Input, AutoFlushMode>, Self::Error> {
+        self.change_mode(Mode::Input)?;
+
+        Ok(Pin {
+            expander: self.expander,
+            id: self.id,
+            bus: PhantomData,
+            mode: PhantomData,
+            access_mode: PhantomData,
+        })
+    }
+
+    /// The initial state is written immediately
+    fn into_output_pin(self, state: PinState) -> Result<Pin<'a, B, R, Output, AutoFlushMode>, Self::Error> {
+        self.change_mode(Mode::Output)?;
+
+        let pin = Pin {
+            expander: self.expander,
+            id: self.id,
+            bus: PhantomData,
+            mode: PhantomData,
+            access_mode: PhantomData,
+        };
+
+        pin.expander
+            .access(|expander| expander.set_state(pin.id, state == PinState::High))?;
+        pin.update_all()?;
+        Ok(pin)
+    }
+}
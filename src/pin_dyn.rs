@@ -0,0 +1,182 @@
+use crate::expander::{Mode, OutputError, PinID, RefreshInputError};
+use crate::guard::RefGuard;
+use crate::pins::{PinError, Pins};
+use core::convert::Infallible;
+use embedded_hal::blocking::i2c::{Read, Write};
+use embedded_hal::digital::v2::{toggleable, InputPin, OutputPin, PinState, StatefulOutputPin};
+
+/// Individual GPIO pin, which mode is stored at runtime
+///
+/// In contrast to [Pin](crate::pins::Pin), the mode is switched without consuming the pin. Using the pin in
+/// the wrong mode, e.g. calling `set_high()` in input mode, fails with [PinError::WrongMode].
+/// The state is updated synchronously, like in [regular access mode](crate::pins::RegularAccessMode).
+///
+/// Each pin can just be taken once at a time, the ownership is released when dropping the pin.
+pub struct DynPin<'a, B, R>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    pins: &'a Pins<B, R>,
+    id: PinID,
+    mode: Mode,
+}
+
+impl<'a, B, R> DynPin<'a, B, R>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    /// Takes the ownership of the given pin
+    pub(crate) fn take(pins: &'a Pins<B, R>, id: PinID) -> Result<Self, PinError<Infallible>> {
+        let mut result = Err(PinError::Taken);
+
+        pins.guard.access(|expander| {
+            if pins.take_ownership(id) {
+                result = Ok(expander.mode(id));
+            }
+        })?;
+
+        Ok(Self {
+            pins,
+            id,
+            mode: result?,
+        })
+    }
+
+    /// Returns the ID of the pin
+    pub fn id(&self) -> PinID {
+        self.id
+    }
+
+    /// Returns the current mode of the pin
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switches the pin to the given mode
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), PinError<OutputError<B>>> {
+        let mut result = Ok(());
+
+        self.pins.guard.access(|expander| {
+            result = expander.set_mode(self.id, mode);
+        })?;
+
+        result.map_err(PinError::BusError)?;
+        self.mode = mode;
+        Ok(())
+    }
+
+    /// Switches the pin to output mode and sets the given output state
+    pub fn set_output_mode(&mut self, state: PinState) -> Result<(), PinError<OutputError<B>>> {
+        self.set_mode(Mode::Output)?;
+        self.set_state(state)
+    }
+
+    fn ensure_mode<E>(&self, mode: Mode) -> Result<(), PinError<E>> {
+        if self.mode != mode {
+            return Err(PinError::WrongMode);
+        }
+
+        Ok(())
+    }
+
+    fn is_pin_output_high(&self) -> Result<bool, PinError<OutputError<B>>> {
+        self.ensure_mode(Mode::Output)?;
+
+        let mut is_high = false;
+        self.pins
+            .guard
+            .access(|expander| is_high = expander.is_pin_output_high(self.id))?;
+
+        Ok(is_high)
+    }
+}
+
+impl<'a, B, R> Drop for DynPin<'a, B, R>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    /// Releases the ownership, even if the expander is accessed at the same time
+    fn drop(&mut self) {
+        self.pins.release_ownership(self.id);
+    }
+}
+
+impl<'a, B, R> InputPin for DynPin<'a, B, R>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    type Error = PinError<RefreshInputError<B>>;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        self.ensure_mode(Mode::Input)?;
+
+        let mut result = Ok(false);
+
+        self.pins.guard.access(|expander| {
+            result = match expander.refresh_input_state() {
+                Ok(_) => Ok(expander.is_pin_input_high(self.id)),
+                Err(error) => Err(error),
+            }
+        })?;
+
+        result.map_err(PinError::BusError)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_high()?)
+    }
+}
+
+impl<'a, B, R> OutputPin for DynPin<'a, B, R>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    type Error = PinError<OutputError<B>>;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::Low)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::High)
+    }
+
+    fn set_state(&mut self, state: PinState) -> Result<(), Self::Error> {
+        self.ensure_mode(Mode::Output)?;
+
+        let mut result = Ok(());
+
+        self.pins.guard.access(|expander| {
+            expander.set_state(self.id, state == PinState::High);
+            result = expander.write_output_state();
+        })?;
+
+        result.map_err(PinError::BusError)
+    }
+}
+
+impl<'a, B, R> StatefulOutputPin for DynPin<'a, B, R>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        self.is_pin_output_high()
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_pin_output_high()?)
+    }
+}
+
+impl<'a, B, R> toggleable::Default for DynPin<'a, B, R>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+}
@@ -0,0 +1,162 @@
+use crate::expander::{OutputError, PinID};
+use crate::guard::{AccessError, DynRefGuard, RefGuard};
+use crate::pin_refreshable::RefreshableOutputPin;
+use crate::pins::{AutoFlushMode, Output, Pin, PinError, RefreshMode, RegularAccessMode};
+use embedded_hal::blocking::i2c::{Read, Write};
+use embedded_hal::digital::v2::{toggleable, OutputPin, PinState, StatefulOutputPin};
+
+/// Access mode of a type-erased pin
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ErasedAccessMode {
+    Regular,
+    Refresh,
+    AutoFlush,
+}
+
+/// Output pin with erased access mode and concurrency guard
+///
+/// In contrast to [Pin], pins of different access modes share the same type. So they can be stored
+/// in arrays or used as `&mut dyn OutputPin` without boxing.
+pub struct ErasedPin<'a, B>
+where
+    B: Write + Read,
+{
+    pub(crate) expander: &'a dyn DynRefGuard<B>,
+    pub(crate) id: PinID,
+    pub(crate) access_mode: ErasedAccessMode,
+}
+
+impl<'a, B> ErasedPin<'a, B>
+where
+    B: Write + Read,
+{
+    pub(crate) fn new<R: RefGuard<B>>(expander: &'a R, id: PinID, access_mode: ErasedAccessMode) -> Self {
+        Self {
+            expander,
+            id,
+            access_mode,
+        }
+    }
+
+    /// Returns the ID of the pin
+    pub fn id(&self) -> PinID {
+        self.id
+    }
+
+    /// Returns true if the state is just cached and written by calling `update_all()`
+    pub fn is_refreshable(&self) -> bool {
+        self.access_mode == ErasedAccessMode::Refresh
+    }
+
+    /// Returns true if changes are written according to the [auto-flush policy](crate::expander::AutoFlushPolicy)
+    pub fn is_auto_flush(&self) -> bool {
+        self.access_mode == ErasedAccessMode::AutoFlush
+    }
+
+    fn is_pin_output_high(&self) -> Result<bool, AccessError> {
+        let mut is_high = false;
+        self.expander
+            .access_dyn(&mut |expander| is_high = expander.is_pin_output_high(self.id))?;
+
+        Ok(is_high)
+    }
+}
+
+impl<'a, B, R> From<Pin<'a, B, R, Output, RegularAccessMode>> for ErasedPin<'a, B>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    fn from(pin: Pin<'a, B, R, Output, RegularAccessMode>) -> Self {
+        ErasedPin::new(pin.expander, pin.id, ErasedAccessMode::Regular)
+    }
+}
+
+impl<'a, B, R> From<Pin<'a, B, R, Output, RefreshMode>> for ErasedPin<'a, B>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    fn from(pin: Pin<'a, B, R, Output, RefreshMode>) -> Self {
+        ErasedPin::new(pin.expander, pin.id, ErasedAccessMode::Refresh)
+    }
+}
+
+impl<'a, B, R> From<Pin<'a, B, R, Output, AutoFlushMode>> for ErasedPin<'a, B>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    fn from(pin: Pin<'a, B, R, Output, AutoFlushMode>) -> Self {
+        ErasedPin::new(pin.expander, pin.id, ErasedAccessMode::AutoFlush)
+    }
+}
+
+impl<'a, B> OutputPin for ErasedPin<'a, B>
+where
+    B: Read + Write,
+{
+    type Error = PinError<OutputError<B>>;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::Low)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set_state(PinState::High)
+    }
+
+    fn set_state(&mut self, state: PinState) -> Result<(), Self::Error> {
+        if self.access_mode == ErasedAccessMode::Refresh {
+            self.expander.set_shadow_state_dyn(self.id, state == PinState::High)?;
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+
+        self.expander.access_dyn(&mut |expander| {
+            expander.set_state(self.id, state == PinState::High);
+            result = match self.access_mode {
+                ErasedAccessMode::AutoFlush => expander.record_change(),
+                _ => expander.write_output_state(),
+            };
+        })?;
+
+        result.map_err(PinError::BusError)
+    }
+}
+
+impl<'a, B> StatefulOutputPin for ErasedPin<'a, B>
+where
+    B: Write + Read,
+{
+    /// As this is just acting on cached register data, it only fails on reentrant access
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.is_pin_output_high()?)
+    }
+
+    /// As this is just acting on cached register data, it only fails on reentrant access
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.is_pin_output_high()?)
+    }
+}
+
+impl<'a, B> toggleable::Default for ErasedPin<'a, B> where B: Write + Read {}
+
+impl<'a, B> RefreshableOutputPin for ErasedPin<'a, B>
+where
+    B: Write + Read,
+{
+    type Error = PinError<OutputError<B>>;
+
+    /// Updates the output state of all pins
+    fn update_all(&self) -> Result<(), Self::Error> {
+        let mut result = Ok(());
+
+        self.expander.access_dyn(&mut |expander| {
+            result = expander.write_output_state();
+        })?;
+
+        result.map_err(PinError::BusError)
+    }
+}
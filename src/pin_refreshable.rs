@@ -1,6 +1,6 @@
-use crate::expander::{Mode, PinID, RefreshInputError};
+use crate::expander::{Mode, OutputError, PinID, RefreshInputError};
 use crate::guard::RefGuard;
-use crate::pins::{Input, Output, Pin, PinMode, RefreshMode};
+use crate::pins::{Input, Output, Pin, PinError, PinMode, RefreshMode};
 use core::convert::Infallible;
 use core::marker::PhantomData;
 use embedded_hal::blocking::i2c::{Read, Write};
@@ -38,14 +38,14 @@ where
     }
 
     /// Refreshes the input state
-    fn refresh(&self) -> Result<(), RefreshInputError<B>> {
+    fn refresh(&self) -> Result<(), PinError<RefreshInputError<B>>> {
         let mut result = Ok(());
 
         self.expander.access(|expander| {
             result = expander.refresh_input_state();
-        });
+        })?;
 
-        result
+        result.map_err(PinError::BusError)
     }
 }
 
@@ -54,7 +54,7 @@ where
     B: Write + Read,
     R: RefGuard<B>,
 {
-    type Error = RefreshInputError<B>;
+    type Error = PinError<RefreshInputError<B>>;
 
     /// Refreshes the input state of all pins
     fn refresh_all(&self) -> Result<(), Self::Error> {
@@ -67,7 +67,7 @@ where
     B: Write + Read,
     R: RefGuard<B>,
 {
-    type Error = <B as Write>::Error;
+    type Error = PinError<OutputError<B>>;
 
     /// Updates the output state of all pins
     fn update_all(&self) -> Result<(), Self::Error> {
@@ -81,14 +81,14 @@ where
     R: RefGuard<B>,
 {
     /// Writes the output state
-    fn update(&self) -> Result<(), <B as Write>::Error> {
+    fn update(&self) -> Result<(), PinError<OutputError<B>>> {
         let mut result = Ok(());
 
         self.expander.access(|expander| {
             result = expander.write_output_state();
-        });
+        })?;
 
-        result
+        result.map_err(PinError::BusError)
     }
 }
 
@@ -97,14 +97,14 @@ where
     B: Write + Read,
     R: RefGuard<B>,
 {
-    type Error = Infallible;
+    type Error = PinError<Infallible>;
 
     fn is_high(&self) -> Result<bool, Self::Error> {
         let mut state = false;
 
         self.expander.access(|expander| {
             state = expander.is_pin_input_high(self.id);
-        });
+        })?;
 
         Ok(state)
     }
@@ -119,7 +119,7 @@ where
     B: Read + Write,
     R: RefGuard<B>,
 {
-    type Error = Infallible;
+    type Error = PinError<Infallible>;
 
     fn set_low(&mut self) -> Result<(), Self::Error> {
         self.set_state(PinState::Low)
@@ -130,10 +130,7 @@ where
     }
 
     fn set_state(&mut self, state: PinState) -> Result<(), Self::Error> {
-        self.expander.access(|expander| {
-            expander.set_state(self.id, state == PinState::High);
-        });
-
+        self.expander.set_shadow_state(self.id, state == PinState::High)?;
         Ok(())
     }
 }
@@ -144,11 +141,11 @@ where
     R: RefGuard<B>,
 {
     fn is_set_high(&self) -> Result<bool, Self::Error> {
-        Ok(self.is_pin_output_high())
+        Ok(self.is_pin_output_high()?)
     }
 
     fn is_set_low(&self) -> Result<bool, Self::Error> {
-        Ok(!self.is_pin_output_high())
+        Ok(!self.is_pin_output_high()?)
     }
 }
 
@@ -166,7 +163,7 @@ where
     R: RefGuard<B>,
     M: PinMode,
 {
-    type Error = <B as Write>::Error;
+    type Error = PinError<OutputError<B>>;
 
     fn into_input_pin(self) -> Result<Pin<'a, B, R, Input, RefreshMode>, Self::Error> {
         self.change_mode(Mode::Input)?;
@@ -183,7 +180,7 @@ where
     fn into_output_pin(self, state: PinState) -> Result<Pin<'a, B, R, Output, RefreshMode>, Self::Error> {
         self.change_mode(Mode::Output)?;
 
-        let mut pin = Pin {
+        let pin = Pin {
             expander: self.expander,
             id: self.id,
             bus: PhantomData,
@@ -191,7 +188,8 @@ where
             access_mode: PhantomData,
         };
 
-        let _ = pin.set_state(state);
+        pin.expander
+            .access(|expander| expander.set_state(pin.id, state == PinState::High))?;
         pin.update_all()?;
         Ok(pin)
     }
@@ -1,6 +1,6 @@
-use crate::expander::{Mode, PinID, RefreshInputError};
+use crate::expander::{Mode, OutputError, PinID, RefreshInputError};
 use crate::guard::RefGuard;
-use crate::pins::{Input, Output, Pin, PinMode, RegularAccessMode};
+use crate::pins::{Input, Output, Pin, PinError, PinMode, RegularAccessMode};
 use core::marker::PhantomData;
 use embedded_hal::blocking::i2c::{Read, Write};
 use embedded_hal::digital::v2::{toggleable, InputPin, IoPin, OutputPin, PinState, StatefulOutputPin};
@@ -26,7 +26,7 @@ where
     B: Write + Read,
     R: RefGuard<B>,
 {
-    type Error = RefreshInputError<B>;
+    type Error = PinError<RefreshInputError<B>>;
 
     fn is_high(&self) -> Result<bool, Self::Error> {
         let mut result = Ok(false);
@@ -36,9 +36,9 @@ where
                 Ok(_) => Ok(expander.is_pin_input_high(self.id)),
                 Err(error) => Err(error),
             }
-        });
+        })?;
 
-        result
+        result.map_err(PinError::BusError)
     }
 
     fn is_low(&self) -> Result<bool, Self::Error> {
@@ -51,7 +51,7 @@ where
     B: Read + Write,
     R: RefGuard<B>,
 {
-    type Error = <B as Write>::Error;
+    type Error = PinError<OutputError<B>>;
 
     fn set_low(&mut self) -> Result<(), Self::Error> {
         self.set_state(PinState::Low)
@@ -67,9 +67,9 @@ where
         self.expander.access(|expander| {
             expander.set_state(self.id, state == PinState::High);
             result = expander.write_output_state();
-        });
+        })?;
 
-        result
+        result.map_err(PinError::BusError)
     }
 }
 
@@ -78,14 +78,14 @@ where
     B: Write + Read,
     R: RefGuard<B>,
 {
-    /// As this is just acting on cached register data, its in fact Infallible
+    /// As this is just acting on cached register data, it only fails on reentrant access
     fn is_set_high(&self) -> Result<bool, Self::Error> {
-        Ok(self.is_pin_output_high())
+        Ok(self.is_pin_output_high()?)
     }
 
-    /// As this is just acting on cached register data, its in fact Infallible
+    /// As this is just acting on cached register data, it only fails on reentrant access
     fn is_set_low(&self) -> Result<bool, Self::Error> {
-        Ok(!self.is_pin_output_high())
+        Ok(!self.is_pin_output_high()?)
     }
 }
 
@@ -103,7 +103,7 @@ where
     R: RefGuard<B>,
     M: PinMode,
 {
-    type Error = <B as Write>::Error;
+    type Error = PinError<OutputError<B>>;
 
     fn into_input_pin(self) -> Result<Pin<'a, B, R, Input, RegularAccessMode>, Self::Error> {
         self.change_mode(Mode::Input)?;
@@ -2,23 +2,24 @@
 //!
 //! This crate fully implements the [digital::v2 traits of embedded_hal](https://docs.rs/embedded-hal/latest/embedded_hal/digital/v2/index.html).
 //!
-//! Due to the I2C overhead, this module offers two options for state management:
+//! Due to the I2C overhead, this module offers three options for state management:
 //! * [Regular access mode](RegularAccessMode): The state is synchronously updated when calling
-//! state functions like `is_high()`, causing 1:1 I2C operations for each individual call.
+//!   state functions like `is_high()`, causing 1:1 I2C operations for each individual call.
 //! * [Refresh access mode](RefreshMode): Register states are internally cached. Functions like
-//! `is_high()` are just using the cached state. The state is updated explicitly, but for all pins at once.
-//! In the best case, the I2C overhead is reduced to one eighth. See [below examples](#refreshable-access-mode) for more details.
+//!   `is_high()` are just using the cached state. The state is updated explicitly, but for all pins at once.
+//!   In the best case, the I2C overhead is reduced to one eighth. See [below examples](#refreshable-access-mode) for more details.
+//! * [Auto-flush access mode](AutoFlushMode): Output changes are recorded and written automatically, once
+//!   a [FlushScope] drops or the [auto-flush policy](crate::expander::AutoFlushPolicy) is met.
+//!   See [below example](#auto-flush-access-mode) for more details.
 //!
 //! ## Setup
 //! Individual pins can be fetched using [PCA9570](crate::expander::PCA9570) instance.
 //! Different concurrency models are supported, see [Concurrency](#Concurrency) section for more details.
 //! ```
-//! use pca9570::example::DummyI2CBus;
-//! use pca9570::expander::Bank::Bank0;
+//! use pca9570::sim::SimulatedPCA9570;
 //! use pca9570::expander::PCA9570;
-//! use pca9570::expander::PinID::Pin1;
 //!
-//! let i2c_bus = DummyI2CBus::default();
+//! let i2c_bus = SimulatedPCA9570::default();
 //! let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //! let pins = expander.pins();
 //! ```
@@ -27,23 +28,22 @@
 //! The following examples demonstrate using the synchronous regular access mode.
 //! Regular access mode is used when calling `get_pin()` method.
 //! ```
-//!# use pca9570::example::DummyI2CBus;
-//!# use pca9570::expander::Bank::{Bank0, Bank1};
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::PCA9570;
-//!# use pca9570::expander::PinID::{Pin1, Pin2, Pin4};
+//!# use pca9570::expander::PinID::{Pin2, Pin3};
 //!# use embedded_hal::digital::v2::{InputPin, IoPin, PinState, OutputPin};
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //! let pins = expander.pins();
-//! let pin12 = pins.get_pin(Bank1, Pin2);
-//! let mut  pin04 = pins.get_pin(Bank0, Pin4).into_output_pin(PinState::Low).unwrap();
+//! let pin02 = pins.get_pin(Pin2);
+//! let mut  pin03 = pins.get_pin(Pin3).into_output_pin(PinState::Low).unwrap();
 //!
-//! // Fetching input state of Pin12
-//! let is_high = pin12.is_high().unwrap();
+//! // Fetching input state of Pin02
+//! let is_high = pin02.is_high().unwrap();
 //!
-//! // Setting Pin04 to high output state
-//! pin04.set_high().unwrap()
+//! // Setting Pin03 to high output state
+//! pin03.set_high().unwrap()
 //! ```
 //! ### Refreshable access mode
 //! The following examples demonstrate using the refreshable access mode.
@@ -52,49 +52,148 @@
 //! In contrast to the previous method, the state must be explicitly updated/refreshed here.
 //! It does not matter which pin is used to call update/refresh.
 //!
-//! As `is_high()` and `is_low()` are just acting on cached state, calls of this method can not fail.
+//! As `is_high()` and `is_low()` are just acting on cached state, calls of this method just fail on reentrant access.
 //! #### Input example
 //! ```
-//!# use pca9570::example::DummyI2CBus;
-//!# use pca9570::expander::Bank::{Bank0, Bank1};
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::PCA9570;
-//!# use pca9570::expander::PinID::{Pin0, Pin1, Pin2, Pin3, Pin4};
-//!# use embedded_hal::digital::v2::{InputPin, IoPin, PinState, OutputPin};
+//!# use pca9570::expander::PinID::{Pin0, Pin1, Pin2};
+//!# use embedded_hal::digital::v2::InputPin;
 //!# use pca9570::pins::RefreshableInputPin;
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //! let pins = expander.pins();
-//! let pin00 = pins.get_refreshable_pin(Bank0, Pin0);
-//! let pin10 = pins.get_refreshable_pin(Bank1, Pin0);
-//! let pin11 = pins.get_refreshable_pin(Bank1, Pin1);
-//!
-//! // Updates the input state of just Bank1. So input state of Pin10 and Pin11 is now up2date
-//! assert!(pin10.is_high().unwrap());
-//! assert!(pin11.is_low().unwrap());
+//! let pin00 = pins.get_refreshable_pin(Pin0);
+//! let pin01 = pins.get_refreshable_pin(Pin1);
+//! let pin02 = pins.get_refreshable_pin(Pin2);
 //!
+//! // Cached state, not fetched yet
 //! assert!(pin00.is_low().unwrap());
+//!
+//! // Updates the input state of all pins. So input state of Pin00, Pin01 and Pin02 is now up2date
+//! pin01.refresh_all().unwrap();
+//! assert!(pin00.is_high().unwrap());
+//! assert!(pin01.is_high().unwrap());
+//! assert!(pin02.is_high().unwrap());
 //! ```
 //! #### Output example
 //! ```
-//!# use pca9570::example::DummyI2CBus;
-//!# use pca9570::expander::Bank::{Bank0, Bank1};
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::PCA9570;
-//!# use pca9570::expander::PinID::{Pin0, Pin1, Pin2, Pin3, Pin4};
-//!# use embedded_hal::digital::v2::{InputPin, IoPin, PinState, OutputPin};
+//!# use pca9570::expander::PinID::{Pin0, Pin1, Pin2};
+//!# use embedded_hal::digital::v2::{IoPin, PinState, OutputPin};
 //!# use pca9570::pins::RefreshableOutputPin;
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //! let pins = expander.pins();
-//! let mut pin00 = pins.get_refreshable_pin(Bank0, Pin0).into_output_pin(PinState::Low).unwrap();
-//! let mut pin10 = pins.get_refreshable_pin(Bank1, Pin0).into_output_pin(PinState::Low).unwrap();
-//! let mut pin11 = pins.get_refreshable_pin(Bank1, Pin1).into_output_pin(PinState::Low).unwrap();
+//! let mut pin00 = pins.get_refreshable_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+//! let mut pin01 = pins.get_refreshable_pin(Pin1).into_output_pin(PinState::Low).unwrap();
+//! let mut pin02 = pins.get_refreshable_pin(Pin2).into_output_pin(PinState::Low).unwrap();
 //!
 //! pin00.set_low().unwrap();
-//! pin10.set_high().unwrap();
-//! pin11.set_state(PinState::High).unwrap();
+//! pin01.set_high().unwrap();
+//! pin02.set_state(PinState::High).unwrap();
+//!
+//! // Writes the output state of all pins at once
+//! pin00.update_all().unwrap();
+//! ```
+//!
+//! ### Auto-flush access mode
+//! The following example demonstrates using the auto-flush access mode, which is used when calling
+//! `get_auto_flush_pin()` method.
+//!
+//! Like in refreshable access mode, output changes are cached. But they are written automatically, either when
+//! the number of changes or their age exceeds the configured policy, or when a [FlushScope] is dropped.
+//! The age is checked on each pin access. So when using a max. age, [Pins::poll_auto_flush()] needs to be called
+//! periodically, e.g. from a timer, to write changes after the last access.
+//! ```
+//!# use pca9570::sim::SimulatedPCA9570;
+//!# use pca9570::expander::{AutoFlushPolicy, PCA9570};
+//!# use pca9570::expander::PinID::{Pin0, Pin1};
+//!# use embedded_hal::digital::v2::{IoPin, PinState, OutputPin};
+//!#
+//!# let i2c_bus = SimulatedPCA9570::default();
+//!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
+//! // Writes the output register after three changes at the latest
+//! expander.set_auto_flush_policy(AutoFlushPolicy { max_changes: Some(3), max_age: None });
+//!
+//! let pins = expander.pins();
+//! let mut pin00 = pins.get_auto_flush_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+//! let mut pin01 = pins.get_auto_flush_pin(Pin1).into_output_pin(PinState::Low).unwrap();
 //!
+//! {
+//!     let _scope = pins.flush_scope();
+//!     pin00.set_high().unwrap();
+//!     pin01.set_high().unwrap();
+//!
+//!     // Changes are written when leaving the scope
+//! }
+//!
+//! // Writes changes older than the max. age, if any
+//! pins.poll_auto_flush().unwrap();
+//! ```
+//!
+//! ## Type-erased pins
+//! Output pins of all access modes can be converted into [ErasedPin], which erases the access mode and
+//! the concurrency guard. This allows storing them in arrays or passing them as `&mut dyn OutputPin`.
+//! ```
+//!# use pca9570::sim::SimulatedPCA9570;
+//!# use pca9570::expander::Mode::Output;
+//!# use pca9570::expander::PCA9570;
+//!# use pca9570::expander::PinID::{Pin0, Pin1};
+//!# use embedded_hal::digital::v2::{IoPin, PinState, OutputPin};
+//!# use pca9570::pins::ErasedPin;
+//!#
+//!# let i2c_bus = SimulatedPCA9570::default();
+//!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
+//! expander.set_mode_all(Output).unwrap();
+//! let pins = expander.pins();
+//!
+//! // Iterating over all pins
+//! for mut pin in pins.all() {
+//!     pin.set_low().unwrap();
+//! }
+//!
+//! // Mixing access modes
+//! let mut mixed: [ErasedPin<_>; 2] = [
+//!     pins.get_pin(Pin0).into_output_pin(PinState::Low).unwrap().into(),
+//!     pins.get_refreshable_pin(Pin1).into_output_pin(PinState::Low).unwrap().into(),
+//! ];
+//!
+//! for pin in mixed.iter_mut() {
+//!     let pin: &mut dyn OutputPin<Error = _> = pin;
+//!     pin.set_high().unwrap();
+//! }
+//! ```
+//!
+//! ## Runtime mode
+//! Switching the mode of [Pin] consumes it. For switching the direction often, e.g. within state machines,
+//! [DynPin] stores its mode at runtime instead. Using it in the wrong mode fails with [PinError::WrongMode].
+//! ```
+//!# use pca9570::sim::SimulatedPCA9570;
+//!# use pca9570::expander::Mode::{Input, Output};
+//!# use pca9570::expander::PCA9570;
+//!# use pca9570::expander::PinID::Pin0;
+//!# use pca9570::pins::PinError;
+//!# use embedded_hal::digital::v2::{InputPin, OutputPin};
+//!#
+//!# let i2c_bus = SimulatedPCA9570::default();
+//!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
+//! let pins = expander.pins();
+//! let mut pin00 = pins.take_dyn_pin(Pin0).unwrap();
+//!
+//! pin00.set_mode(Output).unwrap();
+//! pin00.set_high().unwrap();
+//! assert!(matches!(pin00.is_high(), Err(PinError::WrongMode)));
+//!
+//! pin00.set_mode(Input).unwrap();
+//! let is_high = pin00.is_high().unwrap();
+//!
+//! // Each pin can just be taken once
+//! assert!(pins.take_dyn_pin(Pin0).is_err());
+//! ```
 //!
 //! ## Concurrency
 //! As the pins are using a shared reference, some kind of concurrency management is required.
@@ -106,11 +205,14 @@
 //! This is the most efficient way of using individual pins
 //! The downside is, that these pins are neither Send or Sync, so can only be used in single-threaded
 //! and interrupt-free applications
+//!
+//! Using a pin while the expander is already accessed, e.g. from within another pin's access, does not panic.
+//! Instead the pin operation fails with [PinError::Reentrant].
 //! ```
-//!# use pca9570::example::DummyI2CBus;
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::PCA9570;
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //! let pins = expander.pins();
 //! ```
@@ -121,11 +223,11 @@
 //!
 //! *Requires activation of `cortex-m` feature*
 //!
-//! ```
-//!# use pca9570::example::DummyI2CBus;
+//! ```no_run
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::PCA9570;
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //!# #[cfg(feature = "cortex-m")]
 //! let pins = expander.pins_cs_mutex();
@@ -140,24 +242,66 @@
 //! *Requires activation of `spin` feature*
 //!
 //! ```
-//!# use pca9570::example::DummyI2CBus;
+//!# use pca9570::sim::SimulatedPCA9570;
 //!# use pca9570::expander::PCA9570;
 //!#
-//!# let i2c_bus = DummyI2CBus::default();
+//!# let i2c_bus = SimulatedPCA9570::default();
 //!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
 //!# #[cfg(feature = "spin")]
 //! let pins = expander.pins_spin_mutex();
 //! ```
-use crate::expander::{Mode, PinID};
-use crate::guard::RefGuard;
+//!
+//! ### Atomic shadow register
+//! Both mutex based containers are also available with an atomic shadow output register. Setting the state
+//! of refreshable output pins is then a lock-free bit update, e.g. from interrupts. Only writing the
+//! output register takes the lock, which is done by calling `flush_pending()`, e.g. from the main loop.
+//!
+//! *Requires activation of `portable-atomic` feature and one of the mutex features*
+//!
+//! ```
+//!# use pca9570::sim::SimulatedPCA9570;
+//!# use pca9570::expander::PCA9570;
+//!# use pca9570::expander::PinID::Pin0;
+//!# use embedded_hal::digital::v2::{IoPin, OutputPin, PinState};
+//!#
+//!# let i2c_bus = SimulatedPCA9570::default();
+//!# let mut  expander = PCA9570::new(i2c_bus, 0x24);
+//!# #[cfg(all(feature = "spin", feature = "portable-atomic"))]
+//!# {
+//! let pins = expander.pins_atomic_spin_mutex();
+//! let mut pin00 = pins.get_refreshable_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+//!
+//! // Lock-free, e.g. within an interrupt handler
+//! pin00.set_high().unwrap();
+//!
+//! // Writes the output register, as Pin00 was changed
+//! assert!(pins.flush_pending().unwrap());
+//!# }
+//! ```
+use crate::expander::{Mode, OutputError, PinID};
+#[cfg(feature = "portable-atomic")]
+use crate::guard::AtomicShadowGuard;
+use crate::guard::{AccessError, RefGuard};
+use crate::pin_erased::ErasedAccessMode;
+use core::convert::Infallible;
+use core::fmt::{Display, Formatter};
 use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU8, Ordering};
 use embedded_hal::blocking::i2c::{Read, Write};
 
+pub use crate::pin_auto_flush::FlushScope;
+pub use crate::pin_dyn::DynPin;
+pub use crate::pin_erased::ErasedPin;
 pub use crate::pin_refreshable::{RefreshableInputPin, RefreshableOutputPin};
 
 /// Container for fetching individual pins
 pub struct Pins<B: Write + Read, R: RefGuard<B>> {
-    guard: R,
+    pub(crate) guard: R,
+
+    /// Bitmap of pins taken as [DynPin]
+    /// On targets without atomic read-modify-write, just load/store is used within the guard.
+    taken: AtomicU8,
+
     bus: PhantomData<fn(B) -> B>,
 }
 
@@ -165,13 +309,14 @@ impl<B: Write + Read, R: RefGuard<B>> Pins<B, R> {
     pub fn new(guard: R) -> Self {
         Self {
             guard,
+            taken: AtomicU8::new(0),
             bus: PhantomData,
         }
     }
 
     /// Returns an individual pin, which state gets updated synchronously
     /// **The library does not prevent multiple parallel instances of the same pin.**
-    pub fn get_pin(&self, id: PinID) -> Pin<B, R, Input, RegularAccessMode> {
+    pub fn get_pin(&self, id: PinID) -> Pin<'_, B, R, Input, RegularAccessMode> {
         Pin::regular(&self.guard, id)
     }
 
@@ -179,17 +324,164 @@ impl<B: Write + Read, R: RefGuard<B>> Pins<B, R> {
     /// The status is explicitly updated. This allows a more efficient status query and assignment,
     /// as the status is only updated once for all pins.
     /// **The library does not prevent multiple parallel instances of the same pin.**
-    pub fn get_refreshable_pin(&self, id: PinID) -> Pin<B, R, Input, RefreshMode> {
+    pub fn get_refreshable_pin(&self, id: PinID) -> Pin<'_, B, R, Input, RefreshMode> {
         Pin::refreshable(&self.guard, id)
     }
+
+    /// Returns an individual pin, which changes are written automatically according to the auto-flush policy
+    /// See [AutoFlushPolicy](crate::expander::AutoFlushPolicy) for more details.
+    /// **The library does not prevent multiple parallel instances of the same pin.**
+    pub fn get_auto_flush_pin(&self, id: PinID) -> Pin<'_, B, R, Input, AutoFlushMode> {
+        Pin::auto_flush(&self.guard, id)
+    }
+
+    /// Returns a scope guard, which writes pending changes of pins in auto-flush access mode when dropped
+    pub fn flush_scope(&self) -> FlushScope<'_, B, R> {
+        FlushScope {
+            expander: &self.guard,
+            bus: PhantomData,
+        }
+    }
+
+    /// Writes pending changes of pins in auto-flush access mode, if due according to the auto-flush policy
+    /// As the policy is just checked when pins are accessed, this needs to be called periodically for
+    /// time based policies.
+    /// Returns true if the output register was written
+    pub fn poll_auto_flush(&self) -> Result<bool, PinError<OutputError<B>>> {
+        let mut result = Ok(false);
+
+        self.guard.access(|expander| {
+            result = expander.flush_if_due();
+        })?;
+
+        result.map_err(PinError::BusError)
+    }
+
+    /// Returns all pins as type-erased output pins, which state gets updated synchronously
+    /// The mode of the pins is not changed, so they need to be switched to output mode beforehand,
+    /// e.g. by calling `set_mode_all(Output)`.
+    /// **The library does not prevent multiple parallel instances of the same pin.**
+    pub fn all(&self) -> [ErasedPin<'_, B>; 4] {
+        [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3]
+            .map(|id| ErasedPin::new(&self.guard, id, ErasedAccessMode::Regular))
+    }
+
+    /// Returns an individual pin, which mode is switched at runtime, s. [DynPin]
+    /// In contrast to the other pins, each pin can just be taken once. Fails with [PinError::Taken], if
+    /// the pin is already taken and not dropped yet.
+    pub fn take_dyn_pin(&self, id: PinID) -> Result<DynPin<'_, B, R>, PinError<Infallible>> {
+        DynPin::take(self, id)
+    }
+
+    /// Marks the given pin as taken, returns false if already taken
+    /// Needs to be called within the guard
+    #[cfg(target_has_atomic = "8")]
+    pub(crate) fn take_ownership(&self, id: PinID) -> bool {
+        let bit = 1 << id as u8;
+        self.taken.fetch_or(bit, Ordering::AcqRel) & bit == 0
+    }
+
+    /// Marks the given pin as taken, returns false if already taken
+    /// Needs to be called within the guard
+    #[cfg(not(target_has_atomic = "8"))]
+    pub(crate) fn take_ownership(&self, id: PinID) -> bool {
+        let taken = self.taken.load(Ordering::Acquire);
+        let bit = 1 << id as u8;
+
+        if taken & bit != 0 {
+            return false;
+        }
+
+        self.taken.store(taken | bit, Ordering::Release);
+        true
+    }
+
+    /// Releases the ownership of the given pin
+    /// Lock-free, so the pin is released even while the expander is accessed, e.g. when dropping a pin
+    /// within the access of another one.
+    #[cfg(target_has_atomic = "8")]
+    pub(crate) fn release_ownership(&self, id: PinID) {
+        self.taken.fetch_and(!(1 << id as u8), Ordering::AcqRel);
+    }
+
+    /// Releases the ownership of the given pin
+    /// Without atomic read-modify-write, the guard serializes the update. If the expander is accessed at
+    /// the same time, the pin is released anyway, instead of remaining taken forever.
+    #[cfg(not(target_has_atomic = "8"))]
+    pub(crate) fn release_ownership(&self, id: PinID) {
+        let release = || {
+            let taken = self.taken.load(Ordering::Acquire);
+            self.taken.store(taken & !(1 << id as u8), Ordering::Release);
+        };
+
+        if self.guard.access(|_| release()).is_err() {
+            release();
+        }
+    }
+}
+
+#[cfg(feature = "portable-atomic")]
+impl<B, R> Pins<B, AtomicShadowGuard<B, R>>
+where
+    B: Write + Read,
+    R: RefGuard<B>,
+{
+    /// Writes the output register, if any pin state was changed since the last flush
+    /// Returns true if the output register was written
+    pub fn flush_pending(&self) -> Result<bool, PinError<OutputError<B>>> {
+        self.guard.flush_pending()
+    }
+}
+
+impl<B: Write + Read, R: RefGuard<B>> Drop for Pins<B, R> {
+    /// Writes the safe state of the expander, if configured
+    fn drop(&mut self) {
+        let _ = self.guard.access(|expander| {
+            let _ = expander.apply_safe_state();
+        });
+    }
+}
+
+/// Error of individual pin operations
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PinError<E> {
+    /// Error of the underlying I2C operation
+    BusError(E),
+    /// The expander is already accessed, e.g. by a nested pin operation
+    Reentrant,
+    /// The pin is used in the wrong mode, e.g. setting the output state of an input pin
+    WrongMode,
+    /// The pin is already taken, s. [DynPin]
+    Taken,
+}
+
+impl<E> From<AccessError> for PinError<E> {
+    fn from(error: AccessError) -> Self {
+        match error {
+            AccessError::Reentrant => PinError::Reentrant,
+        }
+    }
+}
+
+impl<E: Display> Display for PinError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PinError::BusError(error) => error.fmt(f),
+            PinError::Reentrant => f.write_str("Reentrant"),
+            PinError::WrongMode => f.write_str("WrongMode"),
+            PinError::Taken => f.write_str("Taken"),
+        }
+    }
 }
 
 /// Marker trait defining how the state of pins is handled.
 ///
-/// Currently there are two modes supported:
+/// Currently there are three modes supported:
 /// * Regular: State of the pin is synchronously fetched from I2C bus when calling functions like `is_high()`
 /// * Refreshable: State of all pins is refreshed explicitly and functions like `is_high()` are working on a cached state.
-/// This reducing the I2C overhead
+///   This reducing the I2C overhead
+/// * Auto-flush: Output changes are recorded and written automatically, once due according to the auto-flush policy
 pub trait AccessMode {}
 
 /// State of the pin is synchronously fetched from I2C bus
@@ -200,6 +492,11 @@ impl AccessMode for RegularAccessMode {}
 pub struct RefreshMode {}
 impl AccessMode for RefreshMode {}
 
+/// Output changes are recorded and written automatically, either when a [FlushScope] drops or once due
+/// according to the [auto-flush policy](crate::expander::AutoFlushPolicy). Input state is fetched synchronously.
+pub struct AutoFlushMode {}
+impl AccessMode for AutoFlushMode {}
+
 /// Indicates the current pin mode. Either Input or Output.
 pub trait PinMode {}
 
@@ -235,12 +532,12 @@ where
 {
     /// Returns the current output state, this logic is independent from access mode, as it acts in both
     /// cases on cached register state
-    pub(crate) fn is_pin_output_high(&self) -> bool {
+    pub(crate) fn is_pin_output_high(&self) -> Result<bool, AccessError> {
         let mut is_high = false;
         self.expander
-            .access(|expander| is_high = expander.is_pin_output_high(self.id));
+            .access(|expander| is_high = expander.is_pin_output_high(self.id))?;
 
-        is_high
+        Ok(is_high)
     }
 }
 
@@ -252,13 +549,13 @@ where
     A: AccessMode,
 {
     /// Switches the pin to the given mode
-    pub(crate) fn change_mode(&self, mode: Mode) -> Result<(), <B as Write>::Error> {
+    pub(crate) fn change_mode(&self, mode: Mode) -> Result<(), PinError<OutputError<B>>> {
         let mut result = Ok(());
 
         self.expander.access(|expander| {
             result = expander.set_mode(self.id, mode);
-        });
+        })?;
 
-        result
+        result.map_err(PinError::BusError)
     }
 }
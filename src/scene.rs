@@ -0,0 +1,110 @@
+//! # Scenes
+//!
+//! Scenes are named output patterns, e.g. operating modes like "idle", "fill" and "drain". They are
+//! registered by [PCA9570::add_scene()](crate::expander::PCA9570::add_scene) and applied by a single write of
+//! the output register, s. [PCA9570::apply_scene()](crate::expander::PCA9570::apply_scene). So all checks
+//! like [interlocks](crate::interlock) are done for the scene as a whole, before touching the bus.
+//!
+//! A mask limits the scene to some pins, while the other pins keep their state. Patterns and masks are
+//! given as logical 4-bit values, starting with Pin0 at the lowest bit, s. [polarity](crate::expander#polarity).
+//! ```
+//! use pca9570::expander::PCA9570;
+//! use pca9570::scene::Scene;
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! let simulator = SimulatedPCA9570::default();
+//! let mut expander = PCA9570::new(&simulator, 0x24);
+//!
+//! expander.add_scene(Scene::new("idle", 0b0000).unwrap()).unwrap();
+//! expander.add_scene(Scene::new("fill", 0b0101).unwrap()).unwrap();
+//! // Just opens the drain valve of Pin3
+//! expander.add_scene(Scene::masked("drain", 0b1000, 0b1000).unwrap()).unwrap();
+//!
+//! expander.apply_scene("fill").unwrap();
+//! assert_eq!([true, false, true, false], simulator.pins());
+//! assert_eq!(Some("fill"), expander.current_scene());
+//!
+//! expander.apply_scene("drain").unwrap();
+//! assert_eq!([true, false, true, true], simulator.pins());
+//! assert_eq!(Some("drain"), expander.current_scene());
+//! ```
+use crate::config::Label;
+use crate::expander::OUTPUT_MASK;
+use core::fmt::{Display, Formatter};
+
+/// Maximum number of scenes per expander
+pub const MAX_SCENES: usize = 8;
+
+/// Named output pattern, s. [module](crate::scene)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Scene {
+    name: Label,
+
+    /// Logical output states, starting with Pin0
+    pattern: u8,
+
+    /// Pins touched by the scene
+    mask: u8,
+}
+
+impl Scene {
+    /// Creates a scene touching all pins
+    /// Returns None if the name is not a valid [Label].
+    pub fn new(name: &str, pattern: u8) -> Option<Self> {
+        Self::masked(name, pattern, OUTPUT_MASK)
+    }
+
+    /// Creates a scene just touching the pins of the given mask
+    /// Returns None if the name is not a valid [Label].
+    pub fn masked(name: &str, pattern: u8, mask: u8) -> Option<Self> {
+        Some(Self {
+            name: Label::new(name)?,
+            pattern: pattern & mask & OUTPUT_MASK,
+            mask: mask & OUTPUT_MASK,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    pub fn pattern(&self) -> u8 {
+        self.pattern
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    /// Returns the given logical output state with the scene applied
+    pub fn apply_to(&self, output: u8) -> u8 {
+        (output & !self.mask) | self.pattern
+    }
+
+    /// Returns true if the given logical output state matches the scene
+    pub fn matches(&self, output: u8) -> bool {
+        output & self.mask == self.pattern
+    }
+}
+
+/// Error of [PCA9570::apply_scene()](crate::expander::PCA9570::apply_scene)
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SceneError<E> {
+    /// No scene with the given name is registered
+    UnknownScene,
+
+    /// Writing the scene failed or was rejected, e.g. by an interlock rule
+    OutputError(E),
+}
+
+impl<E: Display> Display for SceneError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SceneError::UnknownScene => f.write_str("UnknownScene"),
+            SceneError::OutputError(error) => error.fmt(f),
+        }
+    }
+}
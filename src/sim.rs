@@ -0,0 +1,272 @@
+//! # Simulated PCA9570
+//!
+//! [SimulatedPCA9570] mimics the I2C behavior of a real PCA9570, e.g. for examples and host based tests.
+//! In contrast to mocks, no expectations are needed. Instead the resulting pin levels are inspected.
+//!
+//! * Only the configured 7-bit address is acknowledged, any other address fails with [SimError::Nack]
+//! * The 4-bit output register starts with the power-on default, all outputs high
+//! * Writes update the output register, reads return it. The unused upper bits are ignored on write and
+//!   read as 1.
+//!
+//! Both the simulator and a shared reference to it implement the I2C traits. So the simulator can be
+//! inspected, while being used by the driver.
+//! ```
+//! use pca9570::expander::PCA9570;
+//! use pca9570::expander::PinID::Pin1;
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! let simulator = SimulatedPCA9570::new(0x24);
+//! let mut expander = PCA9570::new(&simulator, 0x24);
+//!
+//! expander.set_state(Pin1, false);
+//! expander.write_output_state().unwrap();
+//!
+//! assert!(!simulator.is_pin_high(Pin1));
+//! assert_eq!(0b0000_1101, simulator.output());
+//! ```
+//! With feature `embedded-storage`, `SimulatedFlash` provides an in-RAM stand-in of NOR flash.
+use crate::expander::{PinID, OUTPUT_MASK};
+use core::cell::Cell;
+use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
+#[cfg(feature = "embedded-storage")]
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Default I2C address of PCA9570
+pub const DEFAULT_ADDRESS: u8 = 0x24;
+
+/// Output register value after power-on, all outputs high
+pub const POWER_ON_DEFAULT: u8 = 0b0000_1111;
+
+/// Errors of the simulated bus
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SimError {
+    /// The address was not acknowledged, as it does not match the address of the simulated device
+    Nack,
+}
+
+/// Behavioral simulation of PCA9570 on I2C level
+pub struct SimulatedPCA9570 {
+    /// 7-bit I2C address
+    address: u8,
+
+    /// 4-bit output register
+    output: Cell<u8>,
+
+    /// Number of acknowledged write transactions
+    writes: Cell<usize>,
+
+    /// Number of acknowledged read transactions
+    reads: Cell<usize>,
+}
+
+impl Default for SimulatedPCA9570 {
+    fn default() -> Self {
+        Self::new(DEFAULT_ADDRESS)
+    }
+}
+
+impl SimulatedPCA9570 {
+    /// Creates a powered-on device with the given 7-bit address
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            output: Cell::new(POWER_ON_DEFAULT),
+            writes: Cell::new(0),
+            reads: Cell::new(0),
+        }
+    }
+
+    /// Returns the 7-bit address of the device
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Returns the 4-bit output register
+    pub fn output(&self) -> u8 {
+        self.output.get()
+    }
+
+    /// Returns true if the given output is high
+    pub fn is_pin_high(&self, id: PinID) -> bool {
+        self.output.get() & (1 << id as u8) != 0
+    }
+
+    /// Returns the pin levels, starting with Pin0
+    pub fn pins(&self) -> [bool; 4] {
+        [PinID::Pin0, PinID::Pin1, PinID::Pin2, PinID::Pin3].map(|id| self.is_pin_high(id))
+    }
+
+    /// Returns the number of acknowledged write transactions
+    pub fn write_count(&self) -> usize {
+        self.writes.get()
+    }
+
+    /// Returns the number of acknowledged read transactions
+    pub fn read_count(&self) -> usize {
+        self.reads.get()
+    }
+
+    /// Simulates a power cycle, which resets the output register to the power-on default
+    pub fn power_cycle(&self) {
+        self.output.set(POWER_ON_DEFAULT);
+    }
+
+    fn write_bytes(&self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), SimError> {
+        self.acknowledge(address)?;
+
+        // Each data byte is latched, so the last one is effective
+        if let Some(value) = bytes.last() {
+            self.output.set(value & OUTPUT_MASK);
+        }
+
+        self.writes.set(self.writes.get() + 1);
+        Ok(())
+    }
+
+    fn read_bytes(&self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), SimError> {
+        self.acknowledge(address)?;
+        buffer.fill(self.output.get() | !OUTPUT_MASK);
+
+        self.reads.set(self.reads.get() + 1);
+        Ok(())
+    }
+
+    fn acknowledge(&self, address: SevenBitAddress) -> Result<(), SimError> {
+        if address != self.address {
+            return Err(SimError::Nack);
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for SimulatedPCA9570 {
+    type Error = SimError;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_bytes(address, bytes)
+    }
+}
+
+impl Read for SimulatedPCA9570 {
+    type Error = SimError;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_bytes(address, buffer)
+    }
+}
+
+impl Write for &SimulatedPCA9570 {
+    type Error = SimError;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_bytes(address, bytes)
+    }
+}
+
+impl Read for &SimulatedPCA9570 {
+    type Error = SimError;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_bytes(address, buffer)
+    }
+}
+
+/// Erase size of [SimulatedFlash] in bytes
+#[cfg(feature = "embedded-storage")]
+pub const FLASH_ERASE_SIZE: usize = 128;
+
+/// In-RAM stand-in of NOR flash, e.g. for testing [persistence](crate::persist)
+///
+/// * Erased bytes read as `0xFF`, erasing is done in sectors of [FLASH_ERASE_SIZE] bytes
+/// * Writes are done in words of 4 bytes and can only clear bits, as on real NOR flash
+/// * Unaligned or out of bounds accesses fail with the matching
+///   [NorFlashErrorKind]
+///
+/// The capacity needs to be a multiple of [FLASH_ERASE_SIZE].
+#[cfg(feature = "embedded-storage")]
+#[derive(Debug, Clone)]
+pub struct SimulatedFlash<const SIZE: usize> {
+    data: [u8; SIZE],
+
+    /// Number of erase operations
+    erases: usize,
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<const SIZE: usize> Default for SimulatedFlash<SIZE> {
+    fn default() -> Self {
+        Self {
+            data: [0xFF; SIZE],
+            erases: 0,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<const SIZE: usize> SimulatedFlash<SIZE> {
+    /// Returns the flash content
+    pub fn data(&self) -> &[u8; SIZE] {
+        &self.data
+    }
+
+    /// Returns the flash content for modification, e.g. to simulate corruption
+    pub fn data_mut(&mut self) -> &mut [u8; SIZE] {
+        &mut self.data
+    }
+
+    /// Returns the number of erase operations
+    pub fn erase_count(&self) -> usize {
+        self.erases
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<const SIZE: usize> ErrorType for SimulatedFlash<SIZE> {
+    type Error = NorFlashErrorKind;
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<const SIZE: usize> ReadNorFlash for SimulatedFlash<SIZE> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<const SIZE: usize> NorFlash for SimulatedFlash<SIZE> {
+    const WRITE_SIZE: usize = 4;
+
+    const ERASE_SIZE: usize = FLASH_ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+
+        self.data[from as usize..to as usize].fill(0xFF);
+        self.erases += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+
+        for (cell, byte) in self.data[offset as usize..].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+//! # Transaction statistics
+//!
+//! With the `stats` feature enabled, each [PCA9570](crate::expander::PCA9570) counts its I2C transactions,
+//! e.g. for field telemetry on the health of the expander. Without the feature, neither memory nor runtime
+//! is spent on the counters.
+//!
+//! Counters saturate instead of wrapping around. The timestamp of the last error requires a clock, s.
+//! [PCA9570::set_clock()](crate::expander::PCA9570::set_clock).
+//! ```
+//! use pca9570::expander::PCA9570;
+//! use pca9570::sim::SimulatedPCA9570;
+//!
+//! let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+//! expander.set_state_all(false).unwrap();
+//! expander.refresh_input_state().unwrap();
+//!
+//! let stats = expander.stats();
+//! assert_eq!(2, stats.writes);
+//! assert_eq!(1, stats.reads);
+//! assert_eq!(0, stats.write_errors);
+//!
+//! expander.reset_stats();
+//! assert_eq!(0, expander.stats().writes);
+//! ```
+
+/// Transaction counters of a single expander
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    /// Number of write transactions, including failed ones
+    pub writes: u32,
+
+    /// Number of read transactions, including failed ones
+    pub reads: u32,
+
+    /// Number of bytes written by successful write transactions
+    pub bytes_written: u32,
+
+    /// Number of bytes read by successful read transactions
+    pub bytes_read: u32,
+
+    /// Number of failed write transactions
+    pub write_errors: u32,
+
+    /// Number of failed read transactions
+    pub read_errors: u32,
+
+    /// Number of transactions repeated after an error
+    pub retries: u32,
+
+    /// Number of output writes skipped, as the device was already up to date
+    pub skipped_writes: u32,
+
+    /// Timestamp of the last failed transaction, if a clock is set
+    pub last_error_at: Option<u64>,
+}
+
+impl Stats {
+    /// Records a write transaction with the given number of bytes
+    pub(crate) fn record_write(&mut self, bytes: usize, is_ok: bool, now: Option<u64>) {
+        self.writes = self.writes.saturating_add(1);
+
+        if is_ok {
+            self.bytes_written = self.bytes_written.saturating_add(bytes as u32);
+        } else {
+            self.write_errors = self.write_errors.saturating_add(1);
+            self.last_error_at = now.or(self.last_error_at);
+        }
+    }
+
+    /// Records a read transaction with the given number of bytes
+    pub(crate) fn record_read(&mut self, bytes: usize, is_ok: bool, now: Option<u64>) {
+        self.reads = self.reads.saturating_add(1);
+
+        if is_ok {
+            self.bytes_read = self.bytes_read.saturating_add(bytes as u32);
+        } else {
+            self.read_errors = self.read_errors.saturating_add(1);
+            self.last_error_at = now.or(self.last_error_at);
+        }
+    }
+
+    /// Records a repeated transaction
+    pub(crate) fn record_retry(&mut self) {
+        self.retries = self.retries.saturating_add(1);
+    }
+
+    /// Records a skipped output write
+    pub(crate) fn record_skipped_write(&mut self) {
+        self.skipped_writes = self.skipped_writes.saturating_add(1);
+    }
+}
@@ -0,0 +1,221 @@
+//! # Testing utilities
+//!
+//! [ExpectationBus] is a no_std I2C bus for unit-testing logic built on top of [PCA9570](crate::expander::PCA9570)
+//! without hand-written I2C mocks. Expectations are given on register level and checked in order:
+//!
+//! * [expect_output](ExpectationBus::expect_output): The output register is written with the given value.
+//!   Only the four used bits are compared.
+//! * [expect_readback](ExpectationBus::expect_readback): The register is read and returns the given value.
+//!   The empty write preceding each read of the driver is accepted implicitly.
+//! * [expect_output_nack](ExpectationBus::expect_output_nack), [expect_readback_nack](ExpectationBus::expect_readback_nack):
+//!   Same as above, but the transaction is not acknowledged. A not acknowledged readback already fails on the
+//!   preceding empty write.
+//!
+//! Unexpected transactions panic immediately, [done](ExpectationBus::done) panics if expectations are left.
+//! Like the simulator, both the bus and a shared reference to it implement the I2C traits.
+//! ```
+//! use pca9570::expander::PCA9570;
+//! use pca9570::expander::PinID::{Pin0, Pin1, Pin2};
+//! use pca9570::testing::ExpectationBus;
+//!
+//! let bus: ExpectationBus = ExpectationBus::new(0x24)
+//!     .expect_output(0b0000_0000)
+//!     .expect_output(0b0000_0101)
+//!     .expect_readback(0b1111_0101);
+//!
+//! let mut expander = PCA9570::new(&bus, 0x24);
+//! expander.set_state_all(false).unwrap();
+//!
+//! expander.set_state(Pin0, true);
+//! expander.set_state(Pin1, false);
+//! expander.set_state(Pin2, true);
+//! expander.write_output_state().unwrap();
+//!
+//! expander.refresh_input_state().unwrap();
+//! assert!(expander.is_pin_input_high(Pin0));
+//!
+//! bus.done();
+//! ```
+use crate::expander::OUTPUT_MASK;
+use core::cell::Cell;
+use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
+
+/// Error returned for expectations, which are not acknowledged
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ExpectationError {
+    /// The transaction was not acknowledged as expected
+    Nack,
+}
+
+/// Expected register level transaction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    /// Output register is written with the given value
+    Output { value: u8, ack: bool },
+
+    /// Register is read, returning the given value
+    Readback { value: u8, ack: bool },
+}
+
+/// I2C bus checking expected register level transactions in order.
+/// The capacity of expectations is given by N.
+pub struct ExpectationBus<const N: usize = 16> {
+    /// Expected 7-bit I2C address
+    address: u8,
+
+    expectations: [Option<Expectation>; N],
+
+    /// Index of the next expectation
+    next: Cell<usize>,
+}
+
+impl<const N: usize> ExpectationBus<N> {
+    /// Creates a bus without any expectations for the given 7-bit address
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            expectations: [None; N],
+            next: Cell::new(0),
+        }
+    }
+
+    /// Appends the given expectation
+    ///
+    /// # Panics
+    /// Panics if the capacity N is exceeded
+    pub fn expect(mut self, expectation: Expectation) -> Self {
+        let slot = self
+            .expectations
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .expect("Capacity of expectations exceeded");
+
+        *slot = Some(expectation);
+        self
+    }
+
+    /// Expects the output register to be written with the given value
+    pub fn expect_output(self, value: u8) -> Self {
+        self.expect(Expectation::Output { value, ack: true })
+    }
+
+    /// Expects the output register to be written with the given value, which is not acknowledged
+    pub fn expect_output_nack(self, value: u8) -> Self {
+        self.expect(Expectation::Output { value, ack: false })
+    }
+
+    /// Expects the register to be read, returning the given value
+    pub fn expect_readback(self, value: u8) -> Self {
+        self.expect(Expectation::Readback { value, ack: true })
+    }
+
+    /// Expects the register to be read, which is not acknowledged
+    pub fn expect_readback_nack(self) -> Self {
+        self.expect(Expectation::Readback { value: 0x0, ack: false })
+    }
+
+    /// Returns the number of expectations not met yet
+    pub fn remaining(&self) -> usize {
+        self.expectations[self.next.get()..].iter().flatten().count()
+    }
+
+    /// Verifies that all expectations are met
+    ///
+    /// # Panics
+    /// Panics if expectations are left
+    pub fn done(&self) {
+        if let Some(expectation) = self.peek() {
+            panic!(
+                "{} expectation(s) not met, next one is {:?}",
+                self.remaining(),
+                expectation
+            );
+        }
+    }
+
+    fn peek(&self) -> Option<Expectation> {
+        self.expectations.get(self.next.get()).copied().flatten()
+    }
+
+    fn write_bytes(&self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), ExpectationError> {
+        self.check_address(address);
+
+        match (self.peek(), bytes.last()) {
+            // Empty write preceding a read, a NACK already ends the expected transaction
+            (Some(Expectation::Readback { ack, .. }), None) => {
+                if !ack {
+                    self.next.set(self.next.get() + 1);
+                }
+
+                Self::acknowledge(ack)
+            }
+            (Some(Expectation::Output { value, ack }), Some(written))
+                if value & OUTPUT_MASK == written & OUTPUT_MASK =>
+            {
+                self.next.set(self.next.get() + 1);
+                Self::acknowledge(ack)
+            }
+            (expectation, _) => panic!("Unexpected write of {:?}, expected {:?}", bytes, expectation),
+        }
+    }
+
+    fn read_bytes(&self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), ExpectationError> {
+        self.check_address(address);
+
+        match self.peek() {
+            Some(Expectation::Readback { value, ack }) => {
+                self.next.set(self.next.get() + 1);
+                Self::acknowledge(ack)?;
+
+                buffer.fill(value);
+                Ok(())
+            }
+            expectation => panic!("Unexpected read, expected {:?}", expectation),
+        }
+    }
+
+    fn check_address(&self, address: SevenBitAddress) {
+        assert_eq!(self.address, address, "Unexpected I2C address");
+    }
+
+    fn acknowledge(ack: bool) -> Result<(), ExpectationError> {
+        if ack {
+            Ok(())
+        } else {
+            Err(ExpectationError::Nack)
+        }
+    }
+}
+
+impl<const N: usize> Write for ExpectationBus<N> {
+    type Error = ExpectationError;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_bytes(address, bytes)
+    }
+}
+
+impl<const N: usize> Read for ExpectationBus<N> {
+    type Error = ExpectationError;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_bytes(address, buffer)
+    }
+}
+
+impl<const N: usize> Write for &ExpectationBus<N> {
+    type Error = ExpectationError;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_bytes(address, bytes)
+    }
+}
+
+impl<const N: usize> Read for &ExpectationBus<N> {
+    type Error = ExpectationError;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_bytes(address, buffer)
+    }
+}
@@ -1,26 +1,59 @@
+use crate::config::{ConfigError, ExpanderConfig, Polarity, RetryPolicy, SafeLevel, SafeState};
+use crate::emergency::EmergencyStop;
 use crate::expander::Mode::{Input, Output};
 use crate::expander::PinID::{Pin0, Pin1, Pin2, Pin3};
-use crate::expander::PCA9570;
-#[cfg(not(feature = "spin"))]
-use crate::guard::LockFreeGuard;
+use crate::expander::{AutoFlushPolicy, CapacityError, OutputError, OutputSnapshot, RefreshInputError, PCA9570};
+#[cfg(feature = "serde")]
+use crate::expander::{Mode, PinID};
+use crate::fault::{Fault, FaultError, FaultRates, FaultyBus, Transaction, MAX_SCHEDULED_FAULTS};
+#[cfg(feature = "portable-atomic")]
+use crate::guard::AtomicShadowGuard;
 #[cfg(feature = "spin")]
 use crate::guard::SpinGuard;
+use crate::guard::{AccessError, LockFreeGuard, RefGuard};
+use crate::interlock::{Interlock, MAX_INTERLOCKS};
 use crate::mocks::{BusMockBuilder, MockI2CBus, WriteError};
+use crate::observer::{Fanout, Observer, TransactionError};
+#[cfg(feature = "embedded-storage")]
+use crate::persist::{OutputStore, StoreError, StoredState, LAYOUT_VERSION};
+use crate::pin_erased::ErasedAccessMode;
 use crate::pin_refreshable::{RefreshableInputPin, RefreshableOutputPin};
-use crate::pins::Pins;
-use alloc::string::ToString;
+use crate::pins::{ErasedPin, Pin, PinError, Pins};
+use crate::scene::{Scene, SceneError, MAX_SCENES};
+#[cfg(feature = "embedded-storage")]
+use crate::sim::SimulatedFlash;
+use crate::sim::{SimError, SimulatedPCA9570};
+#[cfg(feature = "stats")]
+use crate::stats::Stats;
+#[cfg(feature = "testing")]
+use crate::testing::{ExpectationBus, ExpectationError};
+#[cfg(feature = "alloc")]
+use crate::trace::{Direction, ParseTraceError, RecordingBus, ReplayBus, ReplayError, Trace, TraceEntry};
+#[cfg(feature = "alloc")]
+use crate::vcd::{OutputChange, VcdRecorder};
+use crate::watchdog::{Watchdog, WatchdogStatus};
+use crate::wear::{CycleCounters, ToggleAction, ToggleLimit};
+#[cfg(feature = "portable-atomic")]
+use core::cell::Cell;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use embedded_hal::blocking::i2c::{Read, Write};
 use embedded_hal::digital::v2::{InputPin, IoPin, OutputPin, PinState, StatefulOutputPin, ToggleableOutputPin};
+#[cfg(feature = "embedded-storage")]
+use embedded_storage::nor_flash::NorFlashErrorKind;
 
 #[test]
 fn test_expander_output_mode() {
-    let i2c_bus = BusMockBuilder::new()
-        .expect_write(1, &[0b1111_0111])
-        .expect_write(1, &[0b1111_0110])
-        .into_mock();
+    // Mode is just cached, the output latch is kept
+    let i2c_bus = BusMockBuilder::new().into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     expander.set_mode(Pin3, Output).unwrap();
     expander.set_mode(Pin0, Output).unwrap();
+    assert_eq!(
+        [Output, Input, Input, Output],
+        [Pin0, Pin1, Pin2, Pin3].map(|id| expander.mode(id))
+    );
 }
 
 #[test]
@@ -33,15 +66,21 @@ fn test_expander_input_mode() {
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     expander.set_mode_all(Output).unwrap();
+    expander.set_state_all(false).unwrap();
+
+    // Input pins are released by writing them high
     expander.set_mode(Pin2, Input).unwrap();
     expander.set_mode(Pin3, Input).unwrap();
+
+    // Already released
+    expander.set_mode(Pin3, Input).unwrap();
 }
 
 #[test]
 fn test_expander_state_low() {
     let i2c_bus = BusMockBuilder::new()
         .expect_write(1, &[0b1111_1101])
-        .expect_write(1, &[0b1111_1011])
+        .expect_write(1, &[0b1111_1001])
         .into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
@@ -69,30 +108,26 @@ fn test_expander_state_high() {
 
 #[test]
 fn test_set_mode_all_input() {
-    let i2c_bus = BusMockBuilder::new()
-        .mock_write(1)
-        .expect_write(1, &[0b1111_1111])
-        .into_mock();
+    let i2c_bus = BusMockBuilder::new().mock_write(1).expect_write(1, &[0b1111_1111]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     expander.set_mode_all(Output).unwrap();
+    expander.set_state_all(false).unwrap();
     expander.set_mode_all(Input).unwrap();
 }
 
 #[test]
 fn test_set_mode_all_output() {
-    let i2c_bus = BusMockBuilder::new().expect_write(1, &[0b0000_0000]).into_mock();
+    let i2c_bus = BusMockBuilder::new().into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     expander.set_mode_all(Output).unwrap();
+    assert_eq!(Output, expander.mode(Pin2));
 }
 
 #[test]
 fn test_set_state_all_low() {
-    let i2c_bus = BusMockBuilder::new()
-        .mock_write(1)
-        .expect_write(1, &[0b0000_0000])
-        .into_mock();
+    let i2c_bus = BusMockBuilder::new().mock_write(1).expect_write(1, &[0b0000_0000]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     expander.set_state_all(true).unwrap();
@@ -110,7 +145,7 @@ fn test_set_state_all_high() {
 #[test]
 fn test_refresh_input_state() {
     let i2c_bus = BusMockBuilder::new()
-        .expect_write(1, &[0x00])
+        .expect_write(1, &[])
         .expect_read(1, 0b0000_0000)
         .into_mock();
 
@@ -120,7 +155,7 @@ fn test_refresh_input_state() {
 
 #[test]
 fn test_refresh_input_state_write_error() {
-    let i2c_bus = BusMockBuilder::new().write_error(0x00).into_mock();
+    let i2c_bus = BusMockBuilder::new().write_error(&[]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let result = expander.refresh_input_state();
@@ -130,7 +165,7 @@ fn test_refresh_input_state_write_error() {
 
 #[test]
 fn test_refresh_input_state_read_error() {
-    let i2c_bus = BusMockBuilder::new().expect_write(1, &[0x00]).read_error().into_mock();
+    let i2c_bus = BusMockBuilder::new().expect_write(1, &[]).read_error().into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let result = expander.refresh_input_state();
@@ -141,7 +176,7 @@ fn test_refresh_input_state_read_error() {
 #[test]
 fn test_is_pin_high() {
     let i2c_bus = BusMockBuilder::new()
-        .expect_write(1, &[0x00])
+        .expect_write(1, &[])
         .expect_read(1, 0b0111_1010)
         .into_mock();
 
@@ -157,7 +192,7 @@ fn test_is_pin_high() {
 #[test]
 fn test_regular_pin_input() {
     let i2c_bus = BusMockBuilder::new()
-        .expect_write(4, &[0x00])
+        .expect_write(4, &[])
         .expect_read(2, 0b0000_0100)
         .expect_read(2, 0b0100_0000)
         .into_mock();
@@ -174,7 +209,7 @@ fn test_regular_pin_input() {
 
 #[test]
 fn test_regular_pin_input_write_error() {
-    let i2c_bus = BusMockBuilder::new().write_error(0x01).into_mock();
+    let i2c_bus = BusMockBuilder::new().write_error(&[]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let pins = get_pins(&mut expander);
@@ -197,7 +232,7 @@ fn test_regular_pin_input_read_error() {
 #[test]
 fn test_refreshable_pin_input() {
     let i2c_bus = BusMockBuilder::new()
-        .expect_write(2, &[0x00])
+        .expect_write(2, &[])
         .expect_read(1, 0b0000_0100)
         .expect_read(1, 0b0100_1000)
         .into_mock();
@@ -221,19 +256,19 @@ fn test_refreshable_pin_input() {
     assert!(!pin03.is_low().unwrap());
 }
 
-
 #[test]
 fn test_refreshable_pin_refresh_all_write_error() {
     let i2c_bus = BusMockBuilder::new()
-        .expect_write(1, &[0x0])
+        .expect_write(1, &[])
         .expect_read(1, 0b0001_0000)
-        .write_error(0x1)
+        .write_error(&[])
         .into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let pins = get_pins(&mut expander);
 
     let pin = pins.get_refreshable_pin(Pin0);
+    pin.refresh_all().unwrap();
     let error = pin.refresh_all().unwrap_err();
 
     assert_eq!("WriteError", error.to_string());
@@ -243,9 +278,9 @@ fn test_refreshable_pin_refresh_all_write_error() {
 #[test]
 fn test_refreshable_pin_refresh_all_read_error() {
     let i2c_bus = BusMockBuilder::new()
-        .expect_write(1, &[0x0])
+        .expect_write(1, &[])
         .expect_read(1, 0b0001_0000)
-        .expect_write(1, &[0x1])
+        .expect_write(1, &[])
         .read_error()
         .into_mock();
 
@@ -253,6 +288,7 @@ fn test_refreshable_pin_refresh_all_read_error() {
     let pins = get_pins(&mut expander);
 
     let pin = pins.get_refreshable_pin(Pin0);
+    pin.refresh_all().unwrap();
     let error = pin.refresh_all().unwrap_err();
 
     assert_eq!("ReadError", error.to_string());
@@ -262,14 +298,14 @@ fn test_refreshable_pin_refresh_all_read_error() {
 #[test]
 fn test_regular_pin_set_output_state() {
     let i2c_bus = BusMockBuilder::new()
-        .mock_write(6) // Mode switch
-        .expect_write(1, &[0x03, 0b1111_1011])
-        .expect_write(1, &[0x02, 0b1110_1111])
-        .expect_write(1, &[0x02, 0b1110_1110])
-        .expect_write(1, &[0x02, 0b1111_1110])
-        .expect_write(1, &[0x02, 0b1111_1110])
-        .expect_write(1, &[0x02, 0b1111_1111])
-        .expect_write(1, &[0x03, 0b1111_1111])
+        .mock_write(3) // Initial states
+        .expect_write(1, &[0b1111_0111])
+        .expect_write(1, &[0b1111_0101])
+        .expect_write(1, &[0b1111_0100])
+        .expect_write(1, &[0b1111_0110])
+        .expect_write(1, &[0b1111_0110])
+        .expect_write(1, &[0b1111_0111])
+        .expect_write(1, &[0b1111_1111])
         .into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
@@ -309,49 +345,55 @@ fn test_regular_pin_set_output_state() {
 
 #[test]
 fn test_regular_pin_set_low_write_error() {
-    let i2c_bus = BusMockBuilder::new().mock_write(2).write_error(0x2).into_mock();
+    let i2c_bus = BusMockBuilder::new().mock_write(1).write_error(&[0b1111_1110]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let pins = get_pins(&mut expander);
     let mut pin = pins.get_pin(Pin0).into_output_pin(PinState::Low).unwrap();
 
     let result = pin.set_low();
-    assert_eq!(WriteError::Error1, result.unwrap_err());
+    assert_eq!(
+        PinError::BusError(OutputError::WriteError(WriteError::Error1)),
+        result.unwrap_err()
+    );
 }
 
 #[test]
 fn test_regular_pin_set_high_write_error() {
-    let i2c_bus = BusMockBuilder::new().mock_write(2).write_error(0x2).into_mock();
+    let i2c_bus = BusMockBuilder::new().mock_write(1).write_error(&[0b1111_1111]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let pins = get_pins(&mut expander);
     let mut pin = pins.get_pin(Pin0).into_output_pin(PinState::Low).unwrap();
 
     let result = pin.set_high();
-    assert_eq!(WriteError::Error1, result.unwrap_err());
+    assert_eq!(
+        PinError::BusError(OutputError::WriteError(WriteError::Error1)),
+        result.unwrap_err()
+    );
 }
 
 #[test]
 fn test_regular_pin_set_state_write_error() {
-    let i2c_bus = BusMockBuilder::new().mock_write(2).write_error(0x2).into_mock();
+    let i2c_bus = BusMockBuilder::new().mock_write(1).write_error(&[0b1111_1111]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let pins = get_pins(&mut expander);
     let mut pin = pins.get_pin(Pin0).into_output_pin(PinState::Low).unwrap();
 
     let result = pin.set_state(PinState::High);
-    assert_eq!(WriteError::Error1, result.unwrap_err());
+    assert_eq!(
+        PinError::BusError(OutputError::WriteError(WriteError::Error1)),
+        result.unwrap_err()
+    );
 }
 
 #[test]
 fn test_refreshable_pin_set_output_state() {
     let i2c_bus = BusMockBuilder::new()
         .mock_write(2) // setting all low
-        .mock_write(16) // mode switch
-        .expect_write(1, &[0x02, 0b0000_0110]) // Update 0
-        .expect_write(1, &[0x03, 0b1110_0000]) // Update Bank 1
-        .expect_write(1, &[0x02, 0b0000_0110]) // Update all
-        .expect_write(1, &[0x03, 0b1110_0000]) // Update all
+        .mock_write(4) // initial states
+        .expect_write(1, &[0b0000_0110]) // Update all
         .into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
@@ -385,11 +427,7 @@ fn test_refreshable_pin_set_output_state() {
 
 #[test]
 fn test_regular_pin_into_output_pin() {
-    let i2c_bus = BusMockBuilder::new()
-        .mock_write(1)
-        .expect_write(1, &[0x06, 0b1111_1110])
-        .expect_write(1, &[0x02, 0b0000_0001])
-        .into_mock();
+    let i2c_bus = BusMockBuilder::new().mock_write(1).expect_write(1, &[0b0000_0001]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     expander.set_state_all(false).unwrap();
@@ -400,8 +438,8 @@ fn test_regular_pin_into_output_pin() {
 #[test]
 fn test_regular_pin_into_input_pin() {
     let i2c_bus = BusMockBuilder::new()
-        .mock_write(2)
-        .expect_write(1, &[0x06, 0b1111_1111])
+        .expect_write(1, &[0b1111_1110])
+        .expect_write(1, &[0b1111_1111]) // Releasing the pin
         .into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
@@ -409,26 +447,15 @@ fn test_regular_pin_into_input_pin() {
     let pins = get_pins(&mut expander);
     let _pin = pins
         .get_pin(Pin0)
-        .into_output_pin(PinState::High)
+        .into_output_pin(PinState::Low)
         .unwrap()
         .into_input_pin()
         .unwrap();
 }
 
-#[test]
-fn test_regular_pin_into_output_pin_mode_switch_error() {
-    let i2c_bus = BusMockBuilder::new().write_error(0x6).into_mock();
-
-    let mut expander = PCA9570::new(i2c_bus, 0x24);
-    let pins = get_pins(&mut expander);
-    let result = pins.get_pin(Pin0).into_output_pin(PinState::High);
-
-    assert!(result.is_err())
-}
-
 #[test]
 fn test_regular_pin_into_output_pin_state_set_error() {
-    let i2c_bus = BusMockBuilder::new().mock_write(1).write_error(0x2).into_mock();
+    let i2c_bus = BusMockBuilder::new().write_error(&[0b1111_1111]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let pins = get_pins(&mut expander);
@@ -439,21 +466,18 @@ fn test_regular_pin_into_output_pin_state_set_error() {
 
 #[test]
 fn test_regular_pin_into_input_pin_mode_error() {
-    let i2c_bus = BusMockBuilder::new().write_error(0x6).into_mock();
+    let i2c_bus = BusMockBuilder::new().mock_write(1).write_error(&[0b1111_1111]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let pins = get_pins(&mut expander);
-    let result = pins.get_pin(Pin0).into_output_pin(PinState::High);
+    let pin = pins.get_pin(Pin0).into_output_pin(PinState::Low).unwrap();
 
-    assert!(result.is_err())
+    assert!(pin.into_input_pin().is_err())
 }
 
 #[test]
 fn test_refreshable_pin_into_output_pin() {
-    let i2c_bus = BusMockBuilder::new()
-        .expect_write(1, &[0b1111_1110])
-        .expect_write(1, &[0b0000_0001])
-        .into_mock();
+    let i2c_bus = BusMockBuilder::new().mock_write(1).expect_write(1, &[0b0000_0001]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     expander.set_state_all(false).unwrap();
@@ -464,8 +488,8 @@ fn test_refreshable_pin_into_output_pin() {
 #[test]
 fn test_refreshable_pin_into_input_pin() {
     let i2c_bus = BusMockBuilder::new()
-        .mock_write(2)
-        .expect_write(1, &[0x06, 0b1111_1111])
+        .expect_write(1, &[0b1111_1110])
+        .expect_write(1, &[0b1111_1111]) // Releasing the pin
         .into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
@@ -473,26 +497,26 @@ fn test_refreshable_pin_into_input_pin() {
     let pins = get_pins(&mut expander);
     let _pin = pins
         .get_refreshable_pin(Pin0)
-        .into_output_pin(PinState::High)
+        .into_output_pin(PinState::Low)
         .unwrap()
         .into_input_pin()
         .unwrap();
 }
 
 #[test]
-fn test_refreshable_pin_into_output_pin_mode_switch_error() {
-    let i2c_bus = BusMockBuilder::new().write_error(0x6).into_mock();
+fn test_refreshable_pin_into_input_pin_mode_error() {
+    let i2c_bus = BusMockBuilder::new().mock_write(1).write_error(&[0b1111_1111]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let pins = get_pins(&mut expander);
-    let result = pins.get_refreshable_pin(Pin0).into_output_pin(PinState::High);
+    let pin = pins.get_refreshable_pin(Pin0).into_output_pin(PinState::Low).unwrap();
 
-    assert!(result.is_err())
+    assert!(pin.into_input_pin().is_err())
 }
 
 #[test]
 fn test_refreshable_pin_into_output_pin_state_set_error() {
-    let i2c_bus = BusMockBuilder::new().mock_write(1).write_error(0x2).into_mock();
+    let i2c_bus = BusMockBuilder::new().write_error(&[0b1111_1111]).into_mock();
 
     let mut expander = PCA9570::new(i2c_bus, 0x24);
     let pins = get_pins(&mut expander);
@@ -501,14 +525,2672 @@ fn test_refreshable_pin_into_output_pin_state_set_error() {
     assert!(result.is_err())
 }
 
+#[test]
+fn test_lock_free_guard_nested_access() {
+    let mut expander = PCA9570::new(BusMockBuilder::new().into_mock(), 0x24);
+    let guard = LockFreeGuard::new(RefCell::new(&mut expander));
+
+    let mut nested_called = false;
+    guard
+        .access(|_| {
+            assert_eq!(Err(AccessError::Reentrant), guard.access(|_| nested_called = true));
+        })
+        .unwrap();
+
+    assert!(!nested_called);
+}
+
+#[test]
+fn test_regular_pin_nested_access() {
+    let i2c_bus = BusMockBuilder::new().mock_write(1).into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    let guard = LockFreeGuard::new(RefCell::new(&mut expander));
+    let input = Pin::regular(&guard, Pin1);
+    let mut output = Pin::regular(&guard, Pin0).into_output_pin(PinState::Low).unwrap();
+
+    guard
+        .access(|_| {
+            assert!(matches!(input.is_high(), Err(PinError::Reentrant)));
+            assert!(matches!(input.is_low(), Err(PinError::Reentrant)));
+            assert_eq!(Err(PinError::Reentrant), output.set_high());
+            assert_eq!(Err(PinError::Reentrant), output.set_state(PinState::Low));
+            assert_eq!(Err(PinError::Reentrant), output.is_set_high());
+            assert_eq!(Err(PinError::Reentrant), output.is_set_low());
+        })
+        .unwrap();
+
+    assert!(output.is_set_low().unwrap());
+}
+
+#[test]
+fn test_regular_pin_nested_mode_switch() {
+    let mut expander = PCA9570::new(BusMockBuilder::new().into_mock(), 0x24);
+    let guard = LockFreeGuard::new(RefCell::new(&mut expander));
+
+    guard
+        .access(|_| {
+            let result = Pin::regular(&guard, Pin2).into_output_pin(PinState::High);
+            assert!(matches!(result, Err(PinError::Reentrant)));
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_refreshable_pin_nested_access() {
+    let i2c_bus = BusMockBuilder::new().mock_write(1).expect_write(1, &[0b1111_1111]).into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    let guard = LockFreeGuard::new(RefCell::new(&mut expander));
+    let input = Pin::refreshable(&guard, Pin1);
+    let mut output = Pin::refreshable(&guard, Pin0).into_output_pin(PinState::Low).unwrap();
+
+    guard
+        .access(|_| {
+            assert_eq!(Err(PinError::Reentrant), input.is_high());
+            assert!(matches!(input.refresh_all(), Err(PinError::Reentrant)));
+            assert_eq!(Err(PinError::Reentrant), output.set_high());
+            assert_eq!(Err(PinError::Reentrant), output.is_set_high());
+            assert_eq!(Err(PinError::Reentrant), output.update_all());
+        })
+        .unwrap();
+
+    assert!(output.is_set_low().unwrap());
+    output.set_high().unwrap();
+    output.update_all().unwrap();
+}
+
+#[cfg(feature = "portable-atomic")]
+#[test]
+fn test_atomic_shadow_set_state_without_lock() {
+    let i2c_bus = BusMockBuilder::new().mock_write(1).expect_write(1, &[0b1111_1111]).into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    let guard = AtomicShadowGuard::new(LockFreeGuard::new(RefCell::new(&mut expander)));
+    let mut pin00 = Pin::refreshable(&guard, Pin0).into_output_pin(PinState::Low).unwrap();
+
+    guard
+        .access(|_| {
+            // Simulates an interrupt preempting while the lock is held
+            pin00.set_high().unwrap();
+        })
+        .unwrap();
+
+    assert!(pin00.is_set_high().unwrap());
+    assert!(guard.is_pending());
+    assert!(guard.flush_pending().unwrap());
+    assert!(!guard.is_pending());
+    assert!(!guard.flush_pending().unwrap());
+}
+
+#[cfg(feature = "portable-atomic")]
+#[test]
+fn test_atomic_shadow_polarity() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_polarity(Pin1, Polarity::ActiveLow);
+
+    let pins = Pins::new(AtomicShadowGuard::new(LockFreeGuard::new(RefCell::new(&mut expander))));
+    let mut pin = pins.get_refreshable_pin(Pin1).into_output_pin(PinState::Low).unwrap();
+
+    pin.set_high().unwrap();
+    assert!(pin.is_set_high().unwrap());
+    assert!(pins.flush_pending().unwrap());
+    assert_eq!([true, false, true, true], simulator.pins());
+}
+
+#[cfg(feature = "portable-atomic")]
+#[test]
+fn test_atomic_shadow_keeps_lock_free_changes() {
+    let i2c_bus = BusMockBuilder::new().mock_write(1).expect_write(1, &[0b0000_0101]).into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    expander.set_state_all(false).unwrap();
+
+    let guard = AtomicShadowGuard::new(LockFreeGuard::new(RefCell::new(&mut expander)));
+    guard
+        .access(|expander| {
+            expander.set_state(Pin2, true);
+
+            // Simulates an interrupt changing another pin, while the closure is running
+            guard.set_shadow_state(Pin0, true).unwrap();
+        })
+        .unwrap();
+
+    assert!(guard.flush_pending().unwrap());
+}
+
+#[cfg(feature = "portable-atomic")]
+thread_local! {
+    /// Simulated interrupt, run once by the next output write
+    static INTERRUPT: Cell<Option<&'static dyn Fn()>> = const { Cell::new(None) };
+}
+
+/// Runs the simulated interrupt while the output register is written
+#[cfg(feature = "portable-atomic")]
+struct InterruptObserver;
+
+#[cfg(feature = "portable-atomic")]
+impl<B: Write + Read> Observer<B> for InterruptObserver {
+    fn on_write(&self, _old: u8, _new: u8, _result: Result<(), &OutputError<B>>) {
+        if let Some(interrupt) = INTERRUPT.with(Cell::take) {
+            interrupt();
+        }
+    }
+}
+
+#[cfg(feature = "portable-atomic")]
+#[test]
+fn test_atomic_shadow_change_during_flush() {
+    static OBSERVER: InterruptObserver = InterruptObserver;
+
+    let simulator: &'static SimulatedPCA9570 = Box::leak(Box::default());
+    let expander = Box::leak(Box::new(PCA9570::new(simulator, 0x24)));
+    expander.set_observer(&OBSERVER);
+    let guard = &*Box::leak(Box::new(AtomicShadowGuard::new(LockFreeGuard::new(RefCell::new(
+        expander,
+    )))));
+
+    guard.set_shadow_state(Pin0, false).unwrap();
+    INTERRUPT.with(|interrupt| {
+        interrupt.set(Some(Box::leak(Box::new(|| {
+            guard.set_shadow_state(Pin0, true).unwrap();
+            guard.set_shadow_state(Pin1, false).unwrap();
+        }))))
+    });
+
+    assert!(guard.flush_pending().unwrap());
+    assert_eq!([false, true, true, true], simulator.pins());
+
+    // Changes of the interrupt are not lost
+    assert!(guard.is_pending());
+    assert!(guard.flush_pending().unwrap());
+    assert_eq!([true, false, true, true], simulator.pins());
+    assert!(!guard.flush_pending().unwrap());
+}
+
+#[cfg(feature = "portable-atomic")]
+#[test]
+fn test_atomic_shadow_flush_error_keeps_pending() {
+    let i2c_bus = BusMockBuilder::new()
+        .mock_write(1)
+        .write_error(&[0b1111_1110])
+        .expect_write(1, &[0b1111_1110])
+        .into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    let pins = Pins::new(AtomicShadowGuard::new(LockFreeGuard::new(RefCell::new(&mut expander))));
+    let mut pin = pins.get_refreshable_pin(Pin0).into_output_pin(PinState::High).unwrap();
+    assert!(!pins.flush_pending().unwrap());
+
+    pin.set_low().unwrap();
+    assert_eq!(
+        PinError::BusError(OutputError::WriteError(WriteError::Error1)),
+        pins.flush_pending().unwrap_err()
+    );
+    assert!(pins.flush_pending().unwrap());
+    assert!(!pins.flush_pending().unwrap());
+}
+
+#[test]
+fn test_erased_pins_all() {
+    let i2c_bus = BusMockBuilder::new()
+        .expect_write(1, &[0b1111_1110])
+        .expect_write(1, &[0b1111_1100])
+        .expect_write(1, &[0b1111_1000])
+        .expect_write(1, &[0b1111_0000])
+        .into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    expander.set_mode_all(Output).unwrap();
+    let pins = get_pins(&mut expander);
+
+    for (index, mut pin) in pins.all().into_iter().enumerate() {
+        assert_eq!(index, pin.id() as usize);
+        assert!(!pin.is_refreshable());
+
+        pin.set_low().unwrap();
+        assert!(pin.is_set_low().unwrap());
+    }
+}
+
+#[test]
+fn test_erased_pins_mixed_access_modes() {
+    let i2c_bus = BusMockBuilder::new()
+        .mock_write(2) // Initial states
+        .expect_write(2, &[0b1111_1111])
+        .into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    let pins = get_pins(&mut expander);
+
+    let mut erased: [ErasedPin<MockI2CBus>; 2] = [
+        pins.get_refreshable_pin(Pin0).into_output_pin(PinState::Low).unwrap().into(),
+        pins.get_pin(Pin1).into_output_pin(PinState::Low).unwrap().into(),
+    ];
+    assert!(erased[0].is_refreshable());
+
+    for pin in erased.iter_mut() {
+        let pin: &mut dyn OutputPin<Error = PinError<OutputError<MockI2CBus>>> = pin;
+        pin.set_high().unwrap();
+    }
+
+    assert!(erased[0].is_set_high().unwrap());
+    assert!(erased[1].is_set_high().unwrap());
+    erased[0].update_all().unwrap();
+}
+
+#[test]
+fn test_erased_auto_flush_pin() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_auto_flush_policy(AutoFlushPolicy {
+        max_changes: Some(2),
+        max_age: None,
+    });
+    let pins = expander.pins();
+
+    let mut erased: [ErasedPin<_>; 2] = [
+        pins.get_auto_flush_pin(Pin0).into_output_pin(PinState::Low).unwrap().into(),
+        pins.get_auto_flush_pin(Pin1).into_output_pin(PinState::Low).unwrap().into(),
+    ];
+    assert!(erased[0].is_auto_flush());
+    assert!(!erased[0].is_refreshable());
+
+    // First change is just recorded
+    erased[0].set_high().unwrap();
+    assert!(erased[0].is_set_high().unwrap());
+    assert_eq!([false, false, true, true], simulator.pins());
+
+    // Second change meets the policy
+    erased[1].set_high().unwrap();
+    assert_eq!([true, true, true, true], simulator.pins());
+}
+
+#[test]
+fn test_erased_pin_nested_access() {
+    let mut expander = PCA9570::new(BusMockBuilder::new().into_mock(), 0x24);
+    let guard = LockFreeGuard::new(RefCell::new(&mut expander));
+    let mut pins = [
+        ErasedPin::new(&guard, Pin0, ErasedAccessMode::Regular),
+        ErasedPin::new(&guard, Pin1, ErasedAccessMode::Refresh),
+        ErasedPin::new(&guard, Pin2, ErasedAccessMode::AutoFlush),
+    ];
+
+    guard
+        .access(|_| {
+            for pin in pins.iter_mut() {
+                assert_eq!(Err(PinError::Reentrant), pin.set_high());
+                assert_eq!(Err(PinError::Reentrant), pin.is_set_high());
+                assert_eq!(Err(PinError::Reentrant), pin.update_all());
+            }
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_dyn_pin_taken_once() {
+    let mut expander = PCA9570::new(BusMockBuilder::new().into_mock(), 0x24);
+    let pins = get_pins(&mut expander);
+
+    let pin00 = pins.take_dyn_pin(Pin0).unwrap();
+    let pin01 = pins.take_dyn_pin(Pin1).unwrap();
+    assert!(matches!(pins.take_dyn_pin(Pin0), Err(PinError::Taken)));
+    assert!(matches!(pins.take_dyn_pin(Pin1), Err(PinError::Taken)));
+
+    drop(pin00);
+    let pin00 = pins.take_dyn_pin(Pin0).unwrap();
+    assert_eq!(Pin0 as u8, pin00.id() as u8);
+    assert_eq!(Pin1 as u8, pin01.id() as u8);
+}
+
+#[test]
+fn test_dyn_pin_drop_while_accessed() {
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+    let pins = expander.pins();
+
+    let mut pin00 = Some(pins.take_dyn_pin(Pin0).unwrap());
+    let pin01 = pins.take_dyn_pin(Pin1).unwrap();
+
+    // Dropping within the access of another pin
+    pins.guard
+        .access(|_| {
+            pin00.take();
+        })
+        .unwrap();
+
+    assert!(pins.take_dyn_pin(Pin0).is_ok());
+    assert!(matches!(pins.take_dyn_pin(Pin1), Err(PinError::Taken)));
+    drop(pin01);
+}
+
+#[test]
+fn test_dyn_pin_switch_mode() {
+    let i2c_bus = BusMockBuilder::new()
+        .expect_write(1, &[0b1111_1101])
+        .expect_write(1, &[0b1111_1111])
+        .expect_write(1, &[])
+        .expect_read(1, 0b0000_0010)
+        .into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    let pins = get_pins(&mut expander);
+    let mut pin = pins.take_dyn_pin(Pin1).unwrap();
+    assert!(pin.mode() == Input);
+
+    pin.set_output_mode(PinState::Low).unwrap();
+    assert!(pin.mode() == Output);
+    assert!(pin.is_set_low().unwrap());
+
+    pin.set_mode(Input).unwrap();
+    assert!(pin.is_high().unwrap());
+}
+
+#[test]
+fn test_dyn_pin_wrong_mode() {
+    let i2c_bus = BusMockBuilder::new().into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    let pins = get_pins(&mut expander);
+    let mut pin = pins.take_dyn_pin(Pin0).unwrap();
+
+    assert_eq!(Err(PinError::WrongMode), pin.set_high());
+    assert_eq!(Err(PinError::WrongMode), pin.set_state(PinState::Low));
+    assert_eq!(Err(PinError::WrongMode), pin.is_set_high());
+    assert_eq!(Err(PinError::WrongMode), pin.toggle());
+
+    pin.set_mode(Output).unwrap();
+    assert!(matches!(pin.is_high(), Err(PinError::WrongMode)));
+    assert!(matches!(pin.is_low(), Err(PinError::WrongMode)));
+}
+
+#[test]
+fn test_dyn_pin_output_write_error() {
+    let i2c_bus = BusMockBuilder::new().write_error(&[0b1111_1110]).into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    let pins = get_pins(&mut expander);
+    let mut pin = pins.take_dyn_pin(Pin0).unwrap();
+    pin.set_mode(Output).unwrap();
+
+    assert_eq!(
+        Err(PinError::BusError(OutputError::WriteError(WriteError::Error1))),
+        pin.set_low()
+    );
+}
+
+#[test]
+fn test_auto_flush_pin_max_changes() {
+    let i2c_bus = BusMockBuilder::new()
+        .mock_write(2) // Initial states
+        .expect_write(1, &[0b1111_1111])
+        .expect_write(1, &[0b1111_1100])
+        .into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    expander.set_auto_flush_policy(AutoFlushPolicy {
+        max_changes: Some(3),
+        max_age: None,
+    });
+
+    let pins = get_pins(&mut expander);
+    let mut pin00 = pins.get_auto_flush_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+    let mut pin01 = pins.get_auto_flush_pin(Pin1).into_output_pin(PinState::Low).unwrap();
+
+    pin00.set_high().unwrap();
+    pin01.set_high().unwrap();
+    assert!(pin00.is_set_high().unwrap());
+    assert!(pin01.is_set_high().unwrap());
+
+    // Third change is triggering the write
+    pin00.set_high().unwrap();
+
+    pin00.set_low().unwrap();
+    pin01.toggle().unwrap();
+    pin01.update_all().unwrap();
+}
+
+#[test]
+fn test_auto_flush_pin_max_age() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let i2c_bus = BusMockBuilder::new()
+        .mock_write(1) // Initial state
+        .expect_write(1, &[0b1111_1111])
+        .into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::SeqCst));
+    expander.set_auto_flush_policy(AutoFlushPolicy {
+        max_changes: None,
+        max_age: Some(100),
+    });
+
+    let pins = get_pins(&mut expander);
+    let mut pin = pins.get_auto_flush_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+
+    NOW.store(1000, Ordering::SeqCst);
+    pin.set_high().unwrap();
+
+    NOW.store(1099, Ordering::SeqCst);
+    assert!(!pins.poll_auto_flush().unwrap());
+    pin.set_high().unwrap();
+
+    NOW.store(1100, Ordering::SeqCst);
+    assert!(pins.poll_auto_flush().unwrap());
+    assert!(!pins.poll_auto_flush().unwrap());
+}
+
+#[test]
+fn test_auto_flush_pin_max_age_on_access() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::SeqCst));
+    expander.set_auto_flush_policy(AutoFlushPolicy {
+        max_changes: None,
+        max_age: Some(100),
+    });
+
+    {
+        let pins = expander.pins();
+        let mut pin00 = pins.get_auto_flush_pin(Pin0).into_output_pin(PinState::High).unwrap();
+        let pin01 = pins.get_auto_flush_pin(Pin1).into_output_pin(PinState::High).unwrap();
+
+        NOW.store(1000, Ordering::SeqCst);
+        pin00.set_low().unwrap();
+
+        NOW.store(1099, Ordering::SeqCst);
+        assert!(pin01.is_set_high().unwrap());
+        assert_eq!([true, true, true, true], simulator.pins());
+
+        // Aged change is written by accessing another pin
+        NOW.store(1100, Ordering::SeqCst);
+        assert!(pin01.is_set_high().unwrap());
+        assert_eq!([false, true, true, true], simulator.pins());
+    }
+
+    // Aged change is written when refreshing the input state
+    NOW.store(2000, Ordering::SeqCst);
+    {
+        let pins = expander.pins();
+        let mut pin02 = pins.get_auto_flush_pin(Pin2).into_output_pin(PinState::High).unwrap();
+        pin02.set_low().unwrap();
+    }
+
+    NOW.store(2100, Ordering::SeqCst);
+    expander.refresh_input_state().unwrap();
+    assert_eq!([false, true, false, true], simulator.pins());
+}
+
+#[test]
+fn test_auto_flush_pin_scope() {
+    let i2c_bus = BusMockBuilder::new()
+        .mock_write(1) // Initial state
+        .expect_write(1, &[0b1111_1111])
+        .into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    let pins = get_pins(&mut expander);
+    let mut pin = pins.get_auto_flush_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+
+    {
+        let _scope = pins.flush_scope();
+        pin.set_high().unwrap();
+        pin.set_high().unwrap();
+    }
+
+    // Nothing pending anymore
+    drop(pins.flush_scope());
+}
+
+#[test]
+fn test_auto_flush_pin_write_error() {
+    let i2c_bus = BusMockBuilder::new()
+        .mock_write(1) // Initial state
+        .write_error(&[0b1111_1111])
+        .into_mock();
+
+    let mut expander = PCA9570::new(i2c_bus, 0x24);
+    expander.set_auto_flush_policy(AutoFlushPolicy {
+        max_changes: Some(1),
+        max_age: None,
+    });
+
+    let pins = get_pins(&mut expander);
+    let mut pin = pins.get_auto_flush_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+
+    assert_eq!(
+        Err(PinError::BusError(OutputError::WriteError(WriteError::Error1))),
+        pin.set_high()
+    );
+}
+
+#[test]
+fn test_sim_power_on_default() {
+    let simulator = SimulatedPCA9570::default();
+
+    assert_eq!(0x24, simulator.address());
+    assert_eq!(0b0000_1111, simulator.output());
+    assert_eq!([true; 4], simulator.pins());
+}
+
+#[test]
+fn test_sim_nack_other_address() {
+    let simulator = SimulatedPCA9570::new(0x24);
+    let mut expander = PCA9570::new(&simulator, 0x25);
+
+    assert_eq!(
+        Err(OutputError::WriteError(SimError::Nack)),
+        expander.set_state_all(false)
+    );
+    assert!(matches!(
+        expander.refresh_input_state(),
+        Err(RefreshInputError::WriteError(SimError::Nack))
+    ));
+
+    assert_eq!(0b0000_1111, simulator.output());
+    assert_eq!(0, simulator.write_count());
+    assert_eq!(0, simulator.read_count());
+}
+
+#[test]
+fn test_sim_read_back() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+
+    expander.set_state_all(false).unwrap();
+    expander.set_state(Pin2, true);
+    expander.write_output_state().unwrap();
+    assert_eq!([false, false, true, false], simulator.pins());
+
+    expander.refresh_input_state().unwrap();
+    assert_eq!(0b1111_0100, expander.input_as_value());
+    assert!(expander.is_pin_input_high(Pin2));
+    assert!(!expander.is_pin_input_high(Pin3));
+
+    assert_eq!(3, simulator.write_count());
+    assert_eq!(1, simulator.read_count());
+}
+
+#[test]
+fn test_sim_last_byte_effective() {
+    let mut simulator = SimulatedPCA9570::default();
+
+    simulator.write(0x24, &[0b0000_0000, 0b1010_0101]).unwrap();
+    assert_eq!(0b0000_0101, simulator.output());
+
+    let mut buffer = [0x0; 2];
+    simulator.read(0x24, &mut buffer).unwrap();
+    assert_eq!([0b1111_0101; 2], buffer);
+
+    simulator.power_cycle();
+    assert_eq!(0b0000_1111, simulator.output());
+}
+
+#[test]
+fn test_sim_pins() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    let pins = expander.pins();
+
+    let mut pin00 = pins.get_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+    let mut pin03 = pins.get_refreshable_pin(Pin3).into_output_pin(PinState::High).unwrap();
+    assert!(!simulator.is_pin_high(Pin0));
+
+    pin00.set_high().unwrap();
+    pin03.set_low().unwrap();
+    assert!(simulator.is_pin_high(Pin0));
+    assert!(simulator.is_pin_high(Pin3));
+
+    pin03.update_all().unwrap();
+    assert!(!simulator.is_pin_high(Pin3));
+}
+
+#[test]
+#[cfg(feature = "example")]
+#[allow(deprecated)]
+fn test_example_dummy_bus() {
+    let mut expander = PCA9570::new(crate::example::DummyI2CBus::default(), 0x24);
+    expander.set_state_all(false).unwrap();
+
+    assert_eq!(0b0000_0000, expander.destroy().output());
+}
+
+#[test]
+fn test_fault_nack_write() {
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator).nack_write(2).unwrap().stall_write(3).unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+
+    expander.set_state_all(false).unwrap();
+    expander.set_state(Pin3, true);
+    assert_eq!(
+        Err(OutputError::WriteError(FaultError::Nack)),
+        expander.write_output_state()
+    );
+    assert_eq!(
+        Err(OutputError::WriteError(FaultError::Timeout)),
+        expander.write_output_state()
+    );
+    assert_eq!(0b0000_0000, simulator.output());
+
+    expander.write_output_state().unwrap();
+    assert_eq!(0b0000_1000, simulator.output());
+
+    let bus = expander.destroy();
+    assert_eq!(4, bus.write_count());
+    assert_eq!(2, bus.injected_faults());
+    assert_eq!(2, simulator.write_count());
+}
+
+#[test]
+fn test_fault_read() {
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator)
+        .fail_read(1)
+        .unwrap()
+        .flip_read_bits(2, 0b0000_0010)
+        .unwrap()
+        .nack_read(3)
+        .unwrap()
+        // Read faults are ignored for writes
+        .schedule(Transaction::Write, 1, Fault::ReadError)
+        .unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+
+    expander.set_state_all(false).unwrap();
+
+    assert!(matches!(
+        expander.refresh_input_state(),
+        Err(RefreshInputError::ReadError(FaultError::ReadError))
+    ));
+
+    expander.refresh_input_state().unwrap();
+    assert_eq!(0b1111_0010, expander.input_as_value());
+
+    assert!(matches!(
+        expander.refresh_input_state(),
+        Err(RefreshInputError::ReadError(FaultError::Nack))
+    ));
+
+    expander.refresh_input_state().unwrap();
+    assert_eq!(0b1111_0000, expander.input_as_value());
+
+    let bus = expander.destroy();
+    assert_eq!(4, bus.read_count());
+    assert_eq!(3, bus.injected_faults());
+}
+
+#[test]
+fn test_fault_stall_callback() {
+    static STALLS: AtomicU64 = AtomicU64::new(0);
+
+    let mut bus = FaultyBus::new(SimulatedPCA9570::default()).stall_read(1).unwrap().on_stall(|| {
+        STALLS.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let mut buffer = [0x0; 1];
+    assert_eq!(Err(FaultError::Timeout), bus.read(0x24, &mut buffer));
+    assert_eq!(1, STALLS.load(Ordering::Relaxed));
+
+    bus.read(0x24, &mut buffer).unwrap();
+    assert_eq!(1, STALLS.load(Ordering::Relaxed));
+    assert_eq!(1, bus.inner().read_count());
+}
+
+#[test]
+fn test_fault_inner_bus_error() {
+    let mut bus = FaultyBus::new(SimulatedPCA9570::new(0x25));
+
+    assert_eq!(Err(FaultError::BusError(SimError::Nack)), bus.write(0x24, &[0x0]));
+    assert_eq!(0, bus.injected_faults());
+}
+
+#[test]
+fn test_fault_schedule_capacity() {
+    let mut bus = FaultyBus::new(SimulatedPCA9570::default());
+
+    for nth in 1..=MAX_SCHEDULED_FAULTS {
+        bus = bus.nack_write(nth).unwrap();
+    }
+
+    assert!(matches!(bus.nack_read(1), Err(CapacityError)));
+}
+
+#[test]
+fn test_fault_random_reproducible() {
+    let rates = FaultRates {
+        write_nack: 300,
+        stall: 100,
+        ..FaultRates::default()
+    };
+
+    let mut first = FaultyBus::new(SimulatedPCA9570::default()).with_random(42, rates);
+    let mut second = FaultyBus::new(SimulatedPCA9570::default()).with_random(42, rates);
+
+    for _ in 0..100 {
+        assert_eq!(first.write(0x24, &[0x0]), second.write(0x24, &[0x0]));
+    }
+
+    assert_eq!(first.injected_faults(), second.injected_faults());
+    assert!(first.injected_faults() > 10);
+    assert!(first.injected_faults() < 80);
+    assert_eq!(100, first.inner().write_count() + first.injected_faults());
+}
+
+#[test]
+fn test_fault_random_rates() {
+    let always = FaultRates {
+        bit_flip: 1000,
+        ..FaultRates::default()
+    };
+    let mut bus = FaultyBus::new(SimulatedPCA9570::default()).with_random(0, always);
+
+    for _ in 0..20 {
+        let mut buffer = [0x0; 1];
+        bus.read(0x24, &mut buffer).unwrap();
+        assert_eq!(1, (buffer[0] ^ 0b1111_1111).count_ones());
+        bus.write(0x24, &[0x0F]).unwrap();
+    }
+    assert_eq!(20, bus.injected_faults());
+
+    let mut bus = FaultyBus::new(SimulatedPCA9570::default()).with_random(7, FaultRates::default());
+    for _ in 0..20 {
+        bus.write(0x24, &[0x0]).unwrap();
+    }
+    assert_eq!(0, bus.injected_faults());
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_expectation_bus() {
+    let bus: ExpectationBus = ExpectationBus::new(0x24)
+        .expect_output(0b0000_1111)
+        .expect_output(0b0000_1011)
+        .expect_readback(0b1111_0011)
+        .expect_output(0b0000_0011);
+    let mut expander = PCA9570::new(&bus, 0x24);
+
+    expander.set_state_all(true).unwrap();
+    expander.set_state(Pin2, false);
+    expander.write_output_state().unwrap();
+    {
+        let pins = expander.pins();
+        let pin03 = pins.get_pin(Pin3);
+
+        assert!(pin03.is_low().unwrap());
+        assert_eq!(1, bus.remaining());
+    }
+
+    expander.set_state(Pin3, false);
+    expander.write_output_state().unwrap();
+
+    bus.done();
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_expectation_bus_nack() {
+    let bus: ExpectationBus<4> = ExpectationBus::new(0x24)
+        .expect_output_nack(0b0000_0000)
+        .expect_readback_nack()
+        .expect_readback(0b1111_1110);
+    let mut expander = PCA9570::new(&bus, 0x24);
+
+    assert_eq!(
+        Err(OutputError::WriteError(ExpectationError::Nack)),
+        expander.set_state_all(false)
+    );
+    assert!(matches!(
+        expander.refresh_input_state(),
+        Err(RefreshInputError::WriteError(ExpectationError::Nack))
+    ));
+
+    expander.refresh_input_state().unwrap();
+    assert_eq!(0b1111_1110, expander.input_as_value());
+    bus.done();
+}
+
+#[cfg(feature = "testing")]
+#[test]
+#[should_panic(expected = "1 expectation(s) not met")]
+fn test_expectation_bus_not_done() {
+    let bus: ExpectationBus = ExpectationBus::new(0x24).expect_output(0b0000_0000);
+    bus.done();
+}
+
+#[cfg(feature = "testing")]
+#[test]
+#[should_panic(expected = "Unexpected write")]
+fn test_expectation_bus_unexpected_write() {
+    let bus: ExpectationBus = ExpectationBus::new(0x24).expect_output(0b0000_0000);
+    let mut expander = PCA9570::new(&bus, 0x24);
+
+    let _ = expander.set_state_all(true);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+#[should_panic(expected = "Unexpected I2C address")]
+fn test_expectation_bus_unexpected_address() {
+    let bus: ExpectationBus = ExpectationBus::new(0x24).expect_output(0b0000_0000);
+    let mut expander = PCA9570::new(&bus, 0x25);
+
+    let _ = expander.set_state_all(false);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_trace_recording() {
+    static NOW: AtomicU64 = AtomicU64::new(100);
+
+    let bus = FaultyBus::new(SimulatedPCA9570::default())
+        .nack_write(2)
+        .unwrap()
+        .fail_read(1)
+        .unwrap();
+    let bus = RecordingBus::new(bus).with_clock(|| NOW.fetch_add(10, Ordering::Relaxed));
+    let mut expander = PCA9570::new(bus, 0x24);
+
+    expander.set_state_all(false).unwrap();
+    expander.set_state(Pin1, true);
+    assert!(expander.write_output_state().is_err());
+    expander.write_output_state().unwrap();
+    assert!(expander.refresh_input_state().is_err());
+    expander.refresh_input_state().unwrap();
+
+    let (_, trace) = expander.destroy().into_parts();
+    assert_eq!(
+        "100 W 0x24 00 OK\n\
+         110 W 0x24 02 ERR\n\
+         120 W 0x24 02 OK\n\
+         130 W 0x24 - OK\n\
+         140 R 0x24 - ERR\n\
+         150 W 0x24 - OK\n\
+         160 R 0x24 F2 OK\n",
+        trace.to_string()
+    );
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_trace_parse() {
+    let text = "# golden trace\n\
+                \n\
+                - W 0x24 0F OK\n\
+                  1500 R 0x24 F5 FF ERR\n";
+
+    let trace: Trace = text.parse().unwrap();
+    assert_eq!(2, trace.len());
+    assert_eq!(
+        TraceEntry {
+            timestamp: Some(1500),
+            direction: Direction::Read,
+            address: 0x24,
+            bytes: vec![0xF5, 0xFF],
+            ok: false,
+        },
+        trace.entries()[1]
+    );
+    assert_eq!(trace, trace.to_string().parse().unwrap());
+
+    assert_eq!(
+        Err(ParseTraceError { line: 3 }),
+        "- W 0x24 0F OK\n- W 0x24 0F OK\n- X 0x24 0F OK".parse::<Trace>()
+    );
+    assert_eq!(Err(ParseTraceError { line: 1 }), "- W 0x24 OK".parse::<Trace>());
+    assert_eq!(Err(ParseTraceError { line: 1 }), "- W 0x24 0F FAIL".parse::<Trace>());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_trace_replay() {
+    let trace = "- W 0x24 00 OK\n\
+                 - W 0x24 FF ERR\n\
+                 - W 0x24 - OK\n\
+                 - R 0x24 F3 OK\n\
+                 - W 0x24 - OK\n\
+                 - R 0x24 - ERR\n\
+                 - W 0x24 FF OK\n";
+    let bus = ReplayBus::new(trace.parse().unwrap());
+    let mut expander = PCA9570::new(&bus, 0x24);
+
+    expander.set_state_all(false).unwrap();
+    assert_eq!(
+        Err(OutputError::WriteError(ReplayError::Recorded)),
+        expander.set_state_all(true)
+    );
+
+    expander.refresh_input_state().unwrap();
+    assert_eq!(0b1111_0011, expander.input_as_value());
+    assert!(matches!(
+        expander.refresh_input_state(),
+        Err(RefreshInputError::ReadError(ReplayError::Recorded))
+    ));
+
+    assert_eq!(Err(ReplayError::Incomplete { remaining: 1 }), bus.finish());
+    expander.write_output_state().unwrap();
+    assert_eq!(Ok(()), bus.finish());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_trace_replay_deviation() {
+    let bus = ReplayBus::new("- W 0x24 00 OK\n- W 0x24 01 OK\n".parse().unwrap());
+    let mut expander = PCA9570::new(&bus, 0x24);
+
+    expander.set_state_all(false).unwrap();
+    expander.set_state(Pin2, true);
+
+    let deviation = ReplayError::Deviation {
+        index: 1,
+        actual: TraceEntry {
+            timestamp: None,
+            direction: Direction::Write,
+            address: 0x24,
+            bytes: vec![0x04],
+            ok: false,
+        },
+    };
+    assert_eq!(
+        Err(OutputError::WriteError(deviation.clone())),
+        expander.write_output_state()
+    );
+
+    // First deviation is kept
+    assert!(expander.refresh_input_state().is_err());
+    assert_eq!(Err(deviation), bus.finish());
+    assert_eq!(1, bus.remaining());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_vcd_export() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator).nack_write(2).unwrap();
+    let recorder = VcdRecorder::new(|| NOW.load(Ordering::Relaxed))
+        .with_labels(["a", "b", "c", "d"])
+        .unwrap();
+    let recorder: &'static VcdRecorder = Box::leak(Box::new(recorder));
+    let mut expander = PCA9570::new(bus, 0x24);
+    expander.set_observer(recorder);
+
+    expander.set_state_all(false).unwrap();
+    expander.set_state(Pin1, true);
+
+    NOW.store(5, Ordering::Relaxed);
+    // Failed write is not committed
+    assert!(expander.write_output_state().is_err());
+
+    NOW.store(20, Ordering::Relaxed);
+    expander.write_output_state().unwrap();
+
+    // Unchanged output and reads are not recorded
+    NOW.store(30, Ordering::Relaxed);
+    expander.write_output_state().unwrap();
+    expander.refresh_input_state().unwrap();
+
+    assert_eq!(
+        vec![
+            OutputChange {
+                timestamp: 0,
+                value: 0b0000_0000,
+            },
+            OutputChange {
+                timestamp: 20,
+                value: 0b0000_0010,
+            }
+        ],
+        recorder.history()
+    );
+
+    assert_eq!(
+        "$timescale 1 ms $end\n\
+         $scope module pca9570 $end\n\
+         $var wire 1 ! a $end\n\
+         $var wire 1 \" b $end\n\
+         $var wire 1 # c $end\n\
+         $var wire 1 $ d $end\n\
+         $upscope $end\n\
+         $enddefinitions $end\n\
+         #0\n\
+         $dumpvars\n\
+         0!\n\
+         0\"\n\
+         0#\n\
+         0$\n\
+         $end\n\
+         #20\n\
+         1\"\n",
+        recorder.to_vcd()
+    );
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_vcd_undefined_start() {
+    static NOW: AtomicU64 = AtomicU64::new(100);
+
+    let recorder: &'static VcdRecorder = Box::leak(Box::new(VcdRecorder::new(|| NOW.load(Ordering::Relaxed))));
+    assert!(recorder.to_vcd().ends_with("#0\n$dumpvars\nx!\nx\"\nx#\nx$\n$end\n"));
+
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+    expander.set_observer(recorder);
+    expander.set_state(Pin1, false);
+    expander.set_state(Pin3, false);
+    expander.write_output_state().unwrap();
+    assert!(recorder.to_vcd().ends_with("x$\n$end\n#100\n1!\n0\"\n1#\n0$\n"));
+
+    recorder.clear();
+    assert!(recorder.history().is_empty());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_vcd_invalid_labels() {
+    let recorder = VcdRecorder::new(|| 0);
+    assert!(recorder.with_labels(["relay 1", "b", "c", "d"]).is_none());
+
+    let recorder = VcdRecorder::new(|| 0);
+    assert!(recorder.with_labels(["a", "", "c", "d"]).is_none());
+
+    let recorder = VcdRecorder::new(|| 0);
+    assert!(recorder.with_labels(["a", "b", "c", "d\n"]).is_none());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_vcd_fanout() {
+    static LOG: EventLog = EventLog::new();
+
+    let recorder: &'static VcdRecorder = Box::leak(Box::new(VcdRecorder::new(|| 0)));
+    let fanout: &'static Fanout<VcdRecorder, EventLog> = Box::leak(Box::new(Fanout::new(recorder, &LOG)));
+
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+    expander.set_observer(fanout);
+    expander.set_state_all(false).unwrap();
+    expander.refresh_input_state().unwrap();
+
+    assert_eq!(
+        vec![OutputChange {
+            timestamp: 0,
+            value: 0b0000_0000,
+        }],
+        recorder.history()
+    );
+    assert_eq!(
+        vec![
+            ObservedEvent::Write {
+                old: 0b1111_1111,
+                new: 0b0000_0000,
+                ok: true,
+            },
+            ObservedEvent::Read {
+                value: 0b1111_0000,
+                ok: true,
+            },
+        ],
+        LOG.take()
+    );
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_stats_counters() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let bus = FaultyBus::new(SimulatedPCA9570::default())
+        .nack_write(2)
+        .unwrap()
+        .fail_read(2)
+        .unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::Relaxed));
+
+    expander.set_state_all(false).unwrap();
+
+    NOW.store(10, Ordering::Relaxed);
+    assert!(expander.write_output_state().is_err());
+    expander.refresh_input_state().unwrap();
+
+    NOW.store(20, Ordering::Relaxed);
+    assert!(expander.refresh_input_state().is_err());
+
+    NOW.store(30, Ordering::Relaxed);
+    assert!(!expander.flush_pending().unwrap());
+
+    assert_eq!(
+        Stats {
+            writes: 4,
+            reads: 2,
+            bytes_written: 1,
+            bytes_read: 1,
+            write_errors: 1,
+            read_errors: 1,
+            retries: 0,
+            skipped_writes: 1,
+            last_error_at: Some(20),
+        },
+        expander.stats()
+    );
+
+    expander.reset_stats();
+    assert_eq!(Stats::default(), expander.stats());
+}
+
+#[cfg(all(feature = "stats", feature = "portable-atomic", feature = "spin"))]
+#[test]
+fn test_stats_atomic_skipped_write() {
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+    {
+        let pins = expander.pins_atomic_spin_mutex();
+
+        assert!(!pins.flush_pending().unwrap());
+        assert!(!pins.flush_pending().unwrap());
+    }
+
+    assert_eq!(2, expander.stats().skipped_writes);
+    assert_eq!(0, expander.stats().writes);
+}
+
+#[derive(Debug, PartialEq)]
+enum ObservedEvent {
+    Write { old: u8, new: u8, ok: bool },
+    Read { value: u8, ok: bool },
+    WriteError,
+    ReadError,
+}
+
+struct EventLog(std::sync::Mutex<Vec<ObservedEvent>>);
+
+impl EventLog {
+    const fn new() -> Self {
+        Self(std::sync::Mutex::new(Vec::new()))
+    }
+
+    fn take(&self) -> Vec<ObservedEvent> {
+        core::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl<B: Write + Read> Observer<B> for EventLog {
+    fn on_write(&self, old: u8, new: u8, result: Result<(), &OutputError<B>>) {
+        let ok = result.is_ok();
+        self.0.lock().unwrap().push(ObservedEvent::Write { old, new, ok });
+    }
+
+    fn on_read(&self, value: u8, result: Result<(), &RefreshInputError<B>>) {
+        let ok = result.is_ok();
+        self.0.lock().unwrap().push(ObservedEvent::Read { value, ok });
+    }
+
+    fn on_error(&self, error: TransactionError<'_, B>) {
+        self.0.lock().unwrap().push(match error {
+            TransactionError::Write(_) => ObservedEvent::WriteError,
+            TransactionError::Read(_) => ObservedEvent::ReadError,
+        });
+    }
+}
+
+#[test]
+fn test_observer_expander() {
+    static LOG: EventLog = EventLog::new();
+
+    let bus = FaultyBus::new(SimulatedPCA9570::default())
+        .nack_write(2)
+        .unwrap()
+        .fail_read(1)
+        .unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+    expander.set_observer(&LOG);
+
+    expander.set_state_all(false).unwrap();
+    expander.set_state(Pin0, true);
+    assert!(expander.write_output_state().is_err());
+    expander.write_output_state().unwrap();
+    assert!(expander.refresh_input_state().is_err());
+    expander.refresh_input_state().unwrap();
+
+    assert_eq!(
+        vec![
+            ObservedEvent::Write {
+                old: 0b1111_1111,
+                new: 0b0000_0000,
+                ok: true,
+            },
+            ObservedEvent::WriteError,
+            ObservedEvent::Write {
+                old: 0b0000_0000,
+                new: 0b0000_0001,
+                ok: false,
+            },
+            ObservedEvent::Write {
+                old: 0b0000_0000,
+                new: 0b0000_0001,
+                ok: true,
+            },
+            ObservedEvent::ReadError,
+            ObservedEvent::Read {
+                value: 0b0000_0000,
+                ok: false,
+            },
+            ObservedEvent::Read {
+                value: 0b1111_0001,
+                ok: true,
+            },
+        ],
+        LOG.take()
+    );
+
+    expander.remove_observer();
+    expander.set_state_all(true).unwrap();
+    assert!(LOG.take().is_empty());
+}
+
+#[test]
+fn test_observer_pins() {
+    static LOG: EventLog = EventLog::new();
+
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_observer(&LOG);
+    expander.set_auto_flush_policy(AutoFlushPolicy {
+        max_changes: Some(2),
+        max_age: None,
+    });
+    {
+        let pins = expander.pins();
+        let mut pin00 = pins.get_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+        let mut pin01 = pins.get_refreshable_pin(Pin1).into_output_pin(PinState::Low).unwrap();
+        let mut pin02 = pins.get_auto_flush_pin(Pin2).into_output_pin(PinState::Low).unwrap();
+        let pin03 = pins.get_pin(Pin3);
+        LOG.take();
+
+        pin00.set_high().unwrap();
+        pin01.set_high().unwrap();
+        pin01.update_all().unwrap();
+        pin02.set_high().unwrap();
+        pin02.set_high().unwrap();
+        pin03.is_high().unwrap();
+    }
+
+    assert_eq!(
+        vec![
+            ObservedEvent::Write {
+                old: 0b1111_1000,
+                new: 0b1111_1001,
+                ok: true,
+            },
+            ObservedEvent::Write {
+                old: 0b1111_1001,
+                new: 0b1111_1011,
+                ok: true,
+            },
+            ObservedEvent::Write {
+                old: 0b1111_1011,
+                new: 0b1111_1111,
+                ok: true,
+            },
+            ObservedEvent::Read {
+                value: 0b1111_1111,
+                ok: true,
+            },
+        ],
+        LOG.take()
+    );
+}
+
+#[cfg(feature = "log")]
+#[test]
+fn test_log_transactions() {
+    struct Logger(std::sync::Mutex<Vec<String>>);
+
+    impl log::Log for Logger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() == log::Level::Trace
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: Logger = Logger(std::sync::Mutex::new(Vec::new()));
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    // Unique address, as other tests may log in parallel
+    let bus = FaultyBus::new(SimulatedPCA9570::new(0x61)).nack_write(2).unwrap();
+    let mut expander = PCA9570::new(bus, 0x61);
+    expander.set_state_all(false).unwrap();
+    assert!(expander.set_state_all(true).is_err());
+    expander.refresh_input_state().unwrap();
+
+    let messages: Vec<String> = LOGGER
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|message| message.starts_with("PCA9570 0x61"))
+        .cloned()
+        .collect();
+
+    assert_eq!(
+        vec![
+            "PCA9570 0x61: write [0] OK",
+            "PCA9570 0x61: write [255] failed",
+            "PCA9570 0x61: write [] OK",
+            "PCA9570 0x61: read [240] OK",
+        ],
+        messages
+    );
+}
+
+#[test]
+fn test_debug_pin_id_mode() {
+    assert_eq!("Pin2", format!("{:?}", Pin2));
+    assert_eq!("Output", format!("{:?}", Output));
+    assert_eq!(
+        "AutoFlushPolicy { max_changes: Some(3), max_age: None }",
+        format!(
+            "{:?}",
+            AutoFlushPolicy {
+                max_changes: Some(3),
+                max_age: None,
+            }
+        )
+    );
+}
+
+#[test]
+fn test_diagnostics() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let bus = FaultyBus::new(SimulatedPCA9570::default()).nack_write(3).unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::Relaxed));
+
+    let diagnostics = expander.diagnostics();
+    assert!(diagnostics.is_output_unknown());
+    assert!(diagnostics.is_output_stale());
+    assert!(diagnostics.is_input_unknown());
+
+    expander.set_mode(Pin0, Output).unwrap();
+    expander.set_state_all(false).unwrap();
+    NOW.store(42, Ordering::Relaxed);
+    expander.refresh_input_state().unwrap();
+    expander.set_state(Pin2, true);
+    assert!(expander.write_output_state().is_err());
+
+    let diagnostics = expander.diagnostics();
+    assert_eq!(0x24, diagnostics.address);
+    assert_eq!(0b0000_0100, diagnostics.output);
+    assert_eq!(Some(0b0000_0000), diagnostics.device_output);
+    assert_eq!(0b1111_1110, diagnostics.configuration);
+    assert_eq!(Some(0b1111_0000), diagnostics.input);
+    assert_eq!(Some(42), diagnostics.input_refreshed_at);
+    assert!(diagnostics.last_write_failed);
+    assert!(diagnostics.is_output_stale());
+    assert!(!diagnostics.is_output_unknown());
+    assert!(!diagnostics.is_input_unknown());
+    assert!(Output == diagnostics.mode(Pin0));
+    assert!(Input == diagnostics.mode(Pin1));
+
+    let expected = "PCA9570 at 0x24\n\
+                    Pin  Mode    Output  Device  Input\n\
+                    P0   Output  low     low     low\n\
+                    P1   Input   low     low     low\n\
+                    P2   Input   high    low     low\n\
+                    P3   Input   low     low     low\n\
+                    Output stale: yes, last write failed: yes, pending changes: 0\n\
+                    Input refreshed at: 42";
+    assert!(diagnostics.to_string().starts_with(expected));
+
+    expander.write_output_state().unwrap();
+    assert!(!expander.diagnostics().is_output_stale());
+}
+
+#[test]
+fn test_debug_expander() {
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+    expander.set_state_all(false).unwrap();
+
+    assert_eq!(
+        "PCA9570 { address: 0x24, output: 0b00000000, committed: 0b00000000, configuration: 0b11111111, \
+         polarity: 0b00000000, input: 0b00000000, auto_flush: AutoFlushPolicy { max_changes: None, max_age: None }, \
+         pending_changes: 0, observer: false, .. }",
+        format!("{:?}", expander)
+    );
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+
+    expander.set_mode(Pin1, Output).unwrap();
+    expander.set_state_all(false).unwrap();
+    expander.set_state(Pin2, true);
+
+    let snapshot = expander.snapshot();
+    assert_eq!(
+        OutputSnapshot {
+            outputs: [false, false, true, false],
+            modes: [Input, Output, Input, Input],
+        },
+        snapshot
+    );
+
+    let mut restored = PCA9570::new(&simulator, 0x24);
+    let writes = simulator.write_count();
+    restored.restore(snapshot).unwrap();
+
+    assert_eq!(writes + 1, simulator.write_count());
+    assert_eq!([false, false, true, false], simulator.pins());
+    assert_eq!(snapshot, restored.snapshot());
+    assert!(!restored.has_pending_changes());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_snapshot_serde_round_trip() {
+    let snapshot = OutputSnapshot {
+        outputs: [true, false, false, true],
+        modes: [Output, Output, Input, Output],
+    };
+
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert_eq!(
+        r#"{"outputs":[true,false,false,true],"modes":["Output","Output","Input","Output"]}"#,
+        json
+    );
+    assert_eq!(snapshot, serde_json::from_str(&json).unwrap());
+
+    let toml = toml::to_string(&snapshot).unwrap();
+    assert_eq!(snapshot, toml::from_str(&toml).unwrap());
+
+    assert_eq!(
+        vec![Pin0, Pin3],
+        serde_json::from_str::<Vec<PinID>>(r#"["Pin0", "Pin3"]"#).unwrap()
+    );
+    assert!(serde_json::from_str::<Mode>(r#""Bidirectional""#).is_err());
+}
+
+#[test]
+fn test_with_config() {
+    let simulator = SimulatedPCA9570::default();
+    let config = ExpanderConfig::new(0x24)
+        .output(Pin0, true)
+        .output(Pin1, true)
+        .output(Pin2, false)
+        .polarity(Pin1, Polarity::ActiveLow)
+        .label(Pin0, "pump")
+        .label(Pin2, "valve")
+        .safe_state(SafeState::new().pin(Pin0, SafeLevel::Low))
+        .retry_policy(RetryPolicy {
+            retries: 3,
+            verify: false,
+        });
+
+    let expander = PCA9570::with_config(&simulator, config).unwrap();
+
+    assert_eq!(1, simulator.write_count());
+    assert_eq!([true, false, false, true], simulator.pins());
+    assert!(expander.is_pin_output_high(Pin1));
+    assert_eq!(
+        [Output, Output, Output, Input],
+        [Pin0, Pin1, Pin2, Pin3].map(|id| expander.mode(id))
+    );
+
+    assert_eq!(Some("valve"), expander.label(Pin2));
+    assert_eq!(None, expander.label(Pin1));
+    assert_eq!(Some(Pin0), expander.find_label("pump"));
+    assert_eq!(None, expander.find_label("fan"));
+
+    assert_eq!(
+        RetryPolicy {
+            retries: 3,
+            verify: false
+        },
+        expander.retry_policy()
+    );
+    assert_eq!(SafeLevel::Low, expander.safe_state().unwrap().level(Pin0));
+}
+
+#[test]
+fn test_with_config_no_initial_output() {
+    let simulator = SimulatedPCA9570::default();
+    let config = ExpanderConfig::new(0x24).polarity(Pin0, Polarity::ActiveLow);
+
+    let expander = PCA9570::with_config(&simulator, config).unwrap();
+
+    assert_eq!(0, simulator.write_count());
+    assert!(!expander.is_pin_output_high(Pin0));
+    assert!(expander.diagnostics().is_output_unknown());
+}
+
+#[test]
+fn test_with_config_write_error() {
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator).nack_write(1).unwrap();
+    let config = ExpanderConfig::new(0x24)
+        .output(Pin0, true)
+        .safe_state(SafeState::new().pin(Pin0, SafeLevel::Low));
+
+    let result = PCA9570::with_config(bus, config);
+    assert_eq!(
+        Some(ConfigError::BusError(OutputError::WriteError(FaultError::Nack))),
+        result.err()
+    );
+
+    // Safe state is not written when dropping the failed expander
+    assert_eq!(0, simulator.write_count());
+    assert_eq!([true, true, true, true], simulator.pins());
+}
+
+#[test]
+fn test_with_config_invalid() {
+    let config = ExpanderConfig::new(0x24).output(Pin0, false);
+
+    assert_eq!(
+        Err(ConfigError::InvalidAddress(0x80)),
+        ExpanderConfig::new(0x80).validate()
+    );
+    assert_eq!(
+        Err(ConfigError::InvalidLabel(Pin1)),
+        config.label(Pin1, "main pump").validate()
+    );
+    assert_eq!(
+        Err(ConfigError::InvalidLabel(Pin2)),
+        config.label(Pin2, "an_overly_long_label").validate()
+    );
+    assert_eq!(
+        Err(ConfigError::DuplicateLabel(Pin3)),
+        config.label(Pin0, "pump").label(Pin3, "pump").validate()
+    );
+    assert_eq!(
+        Err(ConfigError::SafeStateOnInput(Pin1)),
+        config.safe_state(SafeState::new().pin(Pin1, SafeLevel::Low)).validate()
+    );
+
+    // Bus is not touched for invalid configs
+    let simulator = SimulatedPCA9570::default();
+    let result = PCA9570::with_config(&simulator, config.label(Pin0, ""));
+    assert_eq!(Some(ConfigError::InvalidLabel(Pin0)), result.err());
+    assert_eq!(0, simulator.write_count());
+
+    let result = PCA9570::with_config(&simulator, ExpanderConfig::new(0x25).output(Pin0, true));
+    assert_eq!(
+        Some(ConfigError::BusError(OutputError::WriteError(SimError::Nack))),
+        result.err()
+    );
+}
+
+#[test]
+fn test_retry_policy() {
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator).nack_write(2).unwrap().nack_write(3).unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+
+    expander.set_state_all(false).unwrap();
+    expander.set_retry_policy(RetryPolicy {
+        retries: 2,
+        verify: false,
+    });
+    expander.set_state(Pin0, true);
+    expander.write_output_state().unwrap();
+
+    assert_eq!([true, false, false, false], simulator.pins());
+    assert_eq!(4, expander.destroy().write_count());
+}
+
+#[test]
+fn test_retry_policy_verify() {
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator)
+        .flip_read_bits(1, 0b0000_0010)
+        .unwrap()
+        .flip_read_bits(2, 0b0000_0010)
+        .unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+    expander.set_retry_policy(RetryPolicy {
+        retries: 1,
+        verify: true,
+    });
+
+    assert_eq!(
+        Err(OutputError::VerifyMismatch {
+            expected: 0b0000_0000,
+            actual: 0b1111_0010
+        }),
+        expander.set_state_all(false)
+    );
+    assert!(expander.diagnostics().last_write_failed);
+
+    #[cfg(feature = "stats")]
+    assert_eq!(1, expander.stats().retries);
+
+    expander.write_output_state().unwrap();
+    assert!(!expander.diagnostics().is_output_stale());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_config_serde_round_trip() {
+    let config = ExpanderConfig::new(0x24)
+        .output(Pin0, true)
+        .polarity(Pin0, Polarity::ActiveLow)
+        .label(Pin0, "pump")
+        .safe_state(SafeState::new().pin(Pin0, SafeLevel::Low))
+        .retry_policy(RetryPolicy {
+            retries: 1,
+            verify: true,
+        });
+
+    let toml = toml::to_string(&config).unwrap();
+    assert_eq!(config, toml::from_str(&toml).unwrap());
+
+    let json = r#"{"address":36,"pins":[{"mode":"Output","label":"pump"},{"mode":"Input"},{"mode":"Input"},{"mode":"Input","initial":false}]}"#;
+    let config: ExpanderConfig = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        Some("pump"),
+        config.pin(Pin0).label.map(|label| label.to_string()).as_deref()
+    );
+    assert_eq!(Err(ConfigError::OutputOnInput(Pin3)), config.validate());
+
+    let json = r#"{"address":36,"pins":[{"mode":"Output","label":"main pump"},{"mode":"Input"},{"mode":"Input"},{"mode":"Input"}]}"#;
+    assert!(serde_json::from_str::<ExpanderConfig>(json).is_err());
+}
+
+#[test]
+fn test_polarity() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_polarity(Pin1, Polarity::ActiveLow);
+    expander.set_polarity(Pin2, Polarity::ActiveLow);
+    expander.set_polarity(Pin2, Polarity::ActiveHigh);
+
+    assert_eq!(Polarity::ActiveLow, expander.polarity(Pin1));
+    assert_eq!(Polarity::ActiveHigh, expander.polarity(Pin2));
+    assert_eq!(0b0000_0010, expander.polarity_mask());
+
+    // Power-on state of active-low pins is not asserted
+    assert!(!expander.is_pin_output_high(Pin1));
+
+    expander.set_state_all(true).unwrap();
+    assert_eq!([true, false, true, true], simulator.pins());
+
+    expander.set_state(Pin1, true);
+    expander.set_state(Pin3, false);
+    expander.write_output_state().unwrap();
+    assert_eq!([true, false, true, false], simulator.pins());
+    assert!(expander.is_pin_output_high(Pin1));
+
+    expander.refresh_input_state().unwrap();
+    assert!(expander.is_pin_input_high(Pin1));
+    assert!(!expander.is_pin_input_high(Pin3));
+    assert_eq!(0b1111_0101, expander.input_as_value());
+
+    let snapshot = expander.snapshot();
+    assert_eq!([true, true, true, false], snapshot.outputs);
+
+    expander.set_state_all(false).unwrap();
+    expander.restore(snapshot).unwrap();
+    assert_eq!([true, false, true, false], simulator.pins());
+}
+
+#[test]
+fn test_polarity_pins() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_polarity(Pin0, Polarity::ActiveLow);
+    expander.set_polarity(Pin3, Polarity::ActiveLow);
+
+    let pins = expander.pins();
+    let mut pin00 = pins.get_pin(Pin0).into_output_pin(PinState::High).unwrap();
+    assert_eq!([false, true, true, true], simulator.pins());
+    assert!(pin00.is_set_high().unwrap());
+
+    pin00.set_low().unwrap();
+    assert_eq!([true, true, true, true], simulator.pins());
+
+    let mut pin01 = pins.get_refreshable_pin(Pin0).into_output_pin(PinState::High).unwrap();
+    pin01.set_high().unwrap();
+    pin01.update_all().unwrap();
+    assert_eq!([false, true, true, true], simulator.pins());
+
+    let pin03 = pins.get_pin(Pin3);
+    assert!(pin03.is_low().unwrap());
+}
+
+#[test]
+fn test_safe_output_value() {
+    let mut expander = PCA9570::with_config(
+        SimulatedPCA9570::default(),
+        ExpanderConfig::new(0x24)
+            .output(Pin0, true)
+            .output(Pin1, true)
+            .output(Pin2, true)
+            .polarity(Pin1, Polarity::ActiveLow)
+            .safe_state(
+                SafeState::new()
+                    .pin(Pin0, SafeLevel::Low)
+                    .pin(Pin1, SafeLevel::Low)
+                    .pin(Pin2, SafeLevel::Hold),
+            ),
+    )
+    .unwrap();
+
+    assert_eq!(Some(0b1111_1110), expander.safe_output_value());
+
+    // Pins on hold keep the last written value
+    expander.set_state(Pin2, false);
+    assert_eq!(Some(0b1111_1110), expander.safe_output_value());
+
+    expander.write_output_state().unwrap();
+    assert_eq!(Some(0b1111_1010), expander.safe_output_value());
+    assert_eq!(
+        None,
+        PCA9570::new(SimulatedPCA9570::default(), 0x24).safe_output_value()
+    );
+}
+
+#[test]
+fn test_interlock_exclusive() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_state_all(false).unwrap();
+    expander.add_interlock(Interlock::exclusive(&[Pin0], &[Pin1, Pin2])).unwrap();
+
+    expander.set_state(Pin0, true);
+    expander.write_output_state().unwrap();
+
+    let writes = simulator.write_count();
+    expander.set_state(Pin2, true);
+    expander.set_state(Pin3, true);
+    assert_eq!(
+        Err(OutputError::Interlock(Interlock::Exclusive(0b0001, 0b0110))),
+        expander.write_output_state()
+    );
+
+    // Bus is not touched and rejected changes are discarded
+    assert_eq!(writes, simulator.write_count());
+    assert_eq!([true, false, false, false], simulator.pins());
+    assert!(!expander.is_pin_output_high(Pin3));
+    assert!(!expander.diagnostics().is_output_stale());
+
+    assert_eq!(
+        Err(OutputError::Interlock(Interlock::Exclusive(0b0001, 0b0110))),
+        expander.set_state_all(true)
+    );
+
+    expander.clear_interlocks();
+    expander.set_state_all(true).unwrap();
+    assert_eq!(0, expander.interlocks().count());
+}
+
+#[test]
+fn test_interlock_mode_switch() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_mode_all(Output).unwrap();
+    expander.set_state_all(false).unwrap();
+    expander.add_interlock(Interlock::exclusive(&[Pin0], &[Pin1])).unwrap();
+
+    // Releasing the pins would drive P0 and P1 high together
+    let writes = simulator.write_count();
+    assert_eq!(
+        Err(OutputError::Interlock(Interlock::Exclusive(0b0001, 0b0010))),
+        expander.set_mode_all(Input)
+    );
+    assert_eq!(writes, simulator.write_count());
+    assert_eq!([false, false, false, false], simulator.pins());
+    assert_eq!(Output, expander.mode(Pin0));
+
+    expander.set_mode(Pin2, Input).unwrap();
+    assert_eq!([false, false, true, false], simulator.pins());
+    assert!(expander.is_pin_output_high(Pin2));
+}
+
+#[test]
+fn test_interlock_requires() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_polarity(Pin3, Polarity::ActiveLow);
+    expander.add_interlock(Interlock::requires(&[Pin2], &[Pin3])).unwrap();
+
+    assert_eq!(Ok(()), expander.check_interlocks(0b0000_0000));
+    assert_eq!(
+        Err(Interlock::Requires(0b0100, 0b1000)),
+        expander.check_interlocks(0b0000_1100)
+    );
+
+    expander.set_state_all(false).unwrap();
+    expander.set_state(Pin2, true);
+    assert!(expander.write_output_state().is_err());
+
+    expander.set_state(Pin2, true);
+    expander.set_state(Pin3, true);
+    expander.write_output_state().unwrap();
+    assert_eq!([false, false, true, false], simulator.pins());
+}
+
+#[test]
+fn test_interlock_pins() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_state_all(false).unwrap();
+    expander.add_interlock(Interlock::exclusive(&[Pin0], &[Pin1])).unwrap();
+
+    let pins = expander.pins();
+    let mut pin00 = pins.get_pin(Pin0).into_output_pin(PinState::High).unwrap();
+    let mut pin01 = pins.get_pin(Pin1).into_output_pin(PinState::Low).unwrap();
+    let rejected = Err(PinError::BusError(OutputError::Interlock(Interlock::Exclusive(
+        0b01, 0b10,
+    ))));
+
+    assert_eq!(rejected, pin01.set_high());
+    assert!(!pin01.is_set_high().unwrap());
+
+    pin00.set_low().unwrap();
+    pin01.set_high().unwrap();
+    assert_eq!([false, true, false, false], simulator.pins());
+
+    // Batched changes are checked when written
+    let mut pin02 = pins.get_auto_flush_pin(Pin0).into_output_pin(PinState::Low).unwrap();
+    {
+        let _scope = pins.flush_scope();
+        pin02.set_high().unwrap();
+    }
+
+    assert_eq!([false, true, false, false], simulator.pins());
+    assert!(!pin02.is_set_high().unwrap());
+}
+
+#[test]
+fn test_interlock_capacity() {
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+
+    for _ in 0..MAX_INTERLOCKS {
+        expander.add_interlock(Interlock::exclusive(&[Pin0], &[Pin1])).unwrap();
+    }
+
+    assert_eq!(
+        Err(CapacityError),
+        expander.add_interlock(Interlock::requires(&[Pin2], &[Pin3]))
+    );
+    assert_eq!(MAX_INTERLOCKS, expander.interlocks().count());
+}
+
+#[test]
+fn test_safe_state_on_drop() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_polarity(Pin1, Polarity::ActiveLow);
+    expander
+        .set_safe_state(
+            SafeState::new()
+                .pin(Pin0, SafeLevel::Low)
+                .pin(Pin1, SafeLevel::Low)
+                .pin(Pin2, SafeLevel::High),
+        )
+        .unwrap();
+
+    expander.set_state_all(true).unwrap();
+    expander.set_state(Pin3, false);
+    drop(expander);
+
+    // Pin3 is on hold, so the unwritten change is discarded
+    assert_eq!([false, true, true, true], simulator.pins());
+
+    let writes = simulator.write_count();
+    drop(PCA9570::new(&simulator, 0x24));
+    assert_eq!(writes, simulator.write_count());
+}
+
+#[test]
+fn test_safe_state_on_destroy() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_safe_state(SafeState::new().pin(Pin0, SafeLevel::Low)).unwrap();
+    expander.set_state_all(true).unwrap();
+
+    let bus = expander.destroy();
+    assert_eq!([false, true, true, true], bus.pins());
+    assert_eq!(2, bus.write_count());
+}
+
+#[test]
+fn test_safe_state_on_pins_drop() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_safe_state(SafeState::new().pin(Pin2, SafeLevel::Low)).unwrap();
+
+    {
+        let pins = expander.pins();
+        let mut pin = pins.get_pin(Pin2).into_output_pin(PinState::High).unwrap();
+        pin.set_high().unwrap();
+    }
+
+    assert_eq!([true, true, false, true], simulator.pins());
+    let writes = simulator.write_count();
+
+    // Device is already in the safe state
+    expander.apply_safe_state().unwrap();
+    drop(expander);
+    assert_eq!(writes, simulator.write_count());
+}
+
+#[test]
+fn test_safe_state_after_errors() {
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator)
+        .nack_write(2)
+        .unwrap()
+        .nack_write(3)
+        .unwrap()
+        .nack_write(4)
+        .unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+    expander
+        .set_safe_state(SafeState::new().pin(Pin0, SafeLevel::Low).after_errors(2))
+        .unwrap();
+    expander.set_state_all(true).unwrap();
+
+    assert!(expander.set_state_all(true).is_err());
+    assert_eq!(1, expander.consecutive_errors());
+
+    // Safe state is written once after the second error, but fails as well
+    assert!(expander.write_output_state().is_err());
+    assert_eq!(3, expander.consecutive_errors());
+    assert_eq!([true, true, true, true], simulator.pins());
+
+    assert!(expander.set_state_all(true).is_ok());
+    assert_eq!(0, expander.consecutive_errors());
+    expander.remove_safe_state();
+}
+
+#[test]
+fn test_safe_state_after_errors_written() {
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator).nack_read(1).unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+    expander
+        .set_safe_state(SafeState::new().pin(Pin3, SafeLevel::Low).after_errors(1))
+        .unwrap();
+
+    assert!(expander.refresh_input_state().is_err());
+    assert_eq!([true, true, true, false], simulator.pins());
+    assert_eq!(0, expander.consecutive_errors());
+}
+
+#[test]
+fn test_emergency_stop_while_guard_held() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_polarity(Pin1, Polarity::ActiveLow);
+    expander.set_mode_all(Output).unwrap();
+    expander.set_state_all(true).unwrap();
+
+    let mut stop = expander.emergency_stop(&simulator);
+    assert_eq!(0b0000_0010, stop.value());
+
+    let pins = expander.pins();
+    let mut pin = pins.get_pin(Pin0).into_output_pin(PinState::High).unwrap();
+
+    pins.guard
+        .access(|_| {
+            // Simulates a panic while the guard is held
+            assert_eq!(Err(PinError::Reentrant), pin.set_high());
+            unsafe { stop.trigger() }.unwrap();
+        })
+        .unwrap();
+
+    assert_eq!([false, true, false, false], simulator.pins());
+}
+
+#[test]
+fn test_emergency_stop_default_modes() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_state_all(true).unwrap();
+
+    // Pins are in input mode by default, but still deasserted
+    let mut stop = expander.emergency_stop(&simulator);
+    assert_eq!(0b0000_0000, stop.value());
+    unsafe { stop.trigger() }.unwrap();
+    assert_eq!([false, false, false, false], simulator.pins());
+}
+
+#[test]
+fn test_emergency_value() {
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+    expander.set_mode(Pin0, Output).unwrap();
+    expander.set_mode(Pin1, Output).unwrap();
+    expander.set_polarity(Pin0, Polarity::ActiveLow);
+
+    // Just active low pins are kept high
+    assert_eq!(0b0000_0001, expander.emergency_value());
+
+    expander.set_safe_state(SafeState::new().pin(Pin0, SafeLevel::High)).unwrap();
+    // Pins on hold keep the last written value
+    assert_eq!(0b1111_1110, expander.emergency_value());
+
+    let stop = EmergencyStop::new(SimulatedPCA9570::default(), 0x24, 0x0);
+    assert_eq!(0x0, stop.value());
+    assert_eq!(0, stop.into_inner().write_count());
+}
+
+#[test]
+fn test_watchdog() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::Relaxed));
+    expander
+        .set_safe_state(SafeState::new().pin(Pin0, SafeLevel::Low).pin(Pin1, SafeLevel::Low))
+        .unwrap();
+    expander.set_state_all(true).unwrap();
+
+    assert_eq!(WatchdogStatus::Disabled, expander.poll().unwrap());
+    expander.feed().unwrap();
+
+    expander.enable_watchdog(Watchdog {
+        timeout: 50,
+        heartbeat: None,
+    });
+    NOW.store(50, Ordering::Relaxed);
+    assert_eq!(WatchdogStatus::Running, expander.poll().unwrap());
+
+    expander.feed().unwrap();
+    NOW.store(101, Ordering::Relaxed);
+    assert_eq!(WatchdogStatus::Expired, expander.poll().unwrap());
+    assert_eq!([false, false, true, true], simulator.pins());
+    assert!(expander.is_watchdog_faulted());
+
+    // Fault is latched, feeding does not clear it
+    expander.feed().unwrap();
+    assert_eq!(WatchdogStatus::Faulted, expander.poll().unwrap());
+
+    let writes = simulator.write_count();
+    assert_eq!(Err(OutputError::WatchdogFault), expander.set_state_all(true));
+    assert_eq!(writes, simulator.write_count());
+    assert!(!expander.is_pin_output_high(Pin0));
+
+    expander.clear_watchdog_fault();
+    assert_eq!(WatchdogStatus::Running, expander.poll().unwrap());
+    expander.set_state_all(true).unwrap();
+
+    NOW.store(1000, Ordering::Relaxed);
+    expander.disable_watchdog();
+    assert_eq!(WatchdogStatus::Disabled, expander.poll().unwrap());
+}
+
+#[test]
+fn test_watchdog_heartbeat() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::Relaxed));
+    expander.set_mode_all(Output).unwrap();
+    expander.set_state_all(false).unwrap();
+    expander.enable_watchdog(Watchdog {
+        timeout: 10,
+        heartbeat: Some(Pin3),
+    });
+
+    expander.feed().unwrap();
+    assert_eq!([false, false, false, true], simulator.pins());
+    expander.feed().unwrap();
+    assert_eq!([false, false, false, false], simulator.pins());
+    expander.feed().unwrap();
+
+    NOW.store(20, Ordering::Relaxed);
+    assert_eq!(WatchdogStatus::Expired, expander.poll().unwrap());
+    assert_eq!([false, false, false, false], simulator.pins());
+
+    // Heartbeat stops while faulted
+    let writes = simulator.write_count();
+    expander.feed().unwrap();
+    assert_eq!(writes, simulator.write_count());
+}
+
+#[test]
+fn test_watchdog_heartbeat_keeps_pending_changes() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_mode_all(Output).unwrap();
+    expander.set_state_all(false).unwrap();
+    expander.enable_watchdog(Watchdog {
+        timeout: 10,
+        heartbeat: Some(Pin3),
+    });
+
+    // Cached change of another pin is not flushed by the heartbeat
+    expander.set_state(Pin0, true);
+    expander.feed().unwrap();
+    assert_eq!([false, false, false, true], simulator.pins());
+    assert!(expander.is_pin_output_high(Pin0));
+    assert!(expander.is_pin_output_high(Pin3));
+
+    expander.write_output_state().unwrap();
+    assert_eq!([true, false, false, true], simulator.pins());
+}
+
+#[test]
+fn test_watchdog_expiry_write_error() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator).nack_write(2).unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::Relaxed));
+    expander.set_mode_all(Output).unwrap();
+    expander.set_state_all(true).unwrap();
+    expander.enable_watchdog(Watchdog {
+        timeout: 10,
+        heartbeat: None,
+    });
+
+    NOW.store(20, Ordering::Relaxed);
+    assert_eq!(Err(OutputError::WriteError(FaultError::Nack)), expander.poll());
+    assert!(expander.is_watchdog_faulted());
+    assert_eq!([true, true, true, true], simulator.pins());
+
+    // Safe state is retried until written
+    assert_eq!(WatchdogStatus::Expired, expander.poll().unwrap());
+    assert_eq!([false, false, false, false], simulator.pins());
+    assert_eq!(WatchdogStatus::Faulted, expander.poll().unwrap());
+}
+
+#[test]
+fn test_watchdog_default_modes() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::Relaxed));
+    expander.set_state_all(true).unwrap();
+    expander.enable_watchdog(Watchdog {
+        timeout: 10,
+        heartbeat: None,
+    });
+
+    // Pins are in input mode by default, but still deasserted
+    NOW.store(20, Ordering::Relaxed);
+    assert_eq!(WatchdogStatus::Expired, expander.poll().unwrap());
+    assert_eq!([false, false, false, false], simulator.pins());
+}
+
+#[test]
+fn test_watchdog_fallback_bypasses_checks() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::Relaxed));
+    expander.set_state_all(true).unwrap();
+    expander.set_safe_state(SafeState::new().pin(Pin3, SafeLevel::Low)).unwrap();
+    expander.add_interlock(Interlock::requires(&[Pin2], &[Pin3])).unwrap();
+    expander.enable_watchdog(Watchdog {
+        timeout: 10,
+        heartbeat: None,
+    });
+
+    // Safe state is written, even though Pin2 on hold requires Pin3
+    NOW.store(20, Ordering::Relaxed);
+    assert_eq!(WatchdogStatus::Expired, expander.poll().unwrap());
+    assert_eq!([true, true, true, false], simulator.pins());
+}
+
+#[test]
+fn test_watchdog_without_clock() {
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+    expander.enable_watchdog(Watchdog {
+        timeout: 0,
+        heartbeat: None,
+    });
+
+    assert_eq!(WatchdogStatus::Running, expander.poll().unwrap());
+}
+
+#[test]
+fn test_current_budget() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_state_all(false).unwrap();
+    expander.set_load_current(Pin0, 20);
+    expander.set_load_current(Pin1, 15);
+    expander.set_load_current(Pin2, 10);
+    assert_eq!(None, expander.current_budget());
+
+    expander.set_state_all(true).unwrap();
+    assert_eq!(45, expander.load_current());
+    expander.set_state_all(false).unwrap();
+    assert_eq!(0, expander.load_current());
+
+    expander.set_current_budget(30);
+    assert_eq!(Some(30), expander.current_budget());
+
+    expander.set_state(Pin0, true);
+    expander.set_state(Pin2, true);
+    expander.write_output_state().unwrap();
+    assert_eq!(30, expander.load_current());
+
+    let writes = simulator.write_count();
+    expander.set_state(Pin1, true);
+    assert_eq!(
+        Err(OutputError::CurrentBudget { load: 45, budget: 30 }),
+        expander.write_output_state()
+    );
+    assert_eq!(writes, simulator.write_count());
+    assert!(!expander.is_pin_output_high(Pin1));
+    assert_eq!(30, expander.load_current());
+
+    // Pin3 has no declared load
+    expander.set_state(Pin3, true);
+    expander.write_output_state().unwrap();
+
+    expander.remove_current_budget();
+    expander.set_state_all(true).unwrap();
+    assert_eq!(45, expander.load_current());
+}
+
+#[test]
+fn test_current_budget_polarity() {
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+    expander.set_polarity(Pin0, Polarity::ActiveLow);
+    expander.set_load_current(Pin0, 20);
+    expander.set_load_current(Pin1, 20);
+    expander.set_current_budget(20);
+
+    // Active low load is drawing current while the pin is low
+    assert_eq!(20, expander.load_current_of(0b0000_0000));
+    assert_eq!(0, expander.load_current_of(0b0000_0001));
+    assert_eq!(40, expander.load_current_of(0b0000_0010));
+
+    expander.set_state_all(false).unwrap();
+    assert_eq!(0, expander.load_current());
+    assert_eq!(
+        Err(OutputError::CurrentBudget { load: 40, budget: 20 }),
+        expander.set_state_all(true)
+    );
+}
+
+#[test]
+fn test_safe_state_checked_when_set() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_state_all(false).unwrap();
+    expander.set_load_current(Pin0, 20);
+    expander.set_load_current(Pin1, 20);
+    expander.set_current_budget(30);
+    expander.add_interlock(Interlock::exclusive(&[Pin2], &[Pin3])).unwrap();
+
+    let safe_state = SafeState::new().pin(Pin0, SafeLevel::High);
+    expander.set_safe_state(safe_state).unwrap();
+
+    assert_eq!(
+        Err(OutputError::CurrentBudget { load: 40, budget: 30 }),
+        expander.set_safe_state(safe_state.pin(Pin1, SafeLevel::High))
+    );
+    assert_eq!(
+        Err(OutputError::Interlock(Interlock::Exclusive(0b0100, 0b1000))),
+        expander.set_safe_state(safe_state.pin(Pin2, SafeLevel::High).pin(Pin3, SafeLevel::High))
+    );
+
+    // Previous safe state is kept
+    assert_eq!(Some(0b0000_0001), expander.safe_output_value());
+}
+
+#[test]
+fn test_cycle_counters() {
+    let simulator = SimulatedPCA9570::default();
+    let bus = FaultyBus::new(&simulator).nack_write(4).unwrap();
+    let mut expander = PCA9570::new(bus, 0x24);
+    expander.set_state_all(false).unwrap();
+    assert_eq!(CycleCounters { cycles: [1; 4] }, expander.cycle_counters());
+
+    // Unchanged pins are not counted
+    expander.set_state_all(false).unwrap();
+    expander.set_state(Pin1, true);
+    expander.write_output_state().unwrap();
+    assert_eq!([1, 2, 1, 1], expander.cycle_counters().cycles);
+
+    // Failed and rejected writes are not counted
+    assert!(expander.set_state_all(true).is_err());
+    expander.add_interlock(Interlock::exclusive(&[Pin2], &[Pin3])).unwrap();
+    assert!(expander.set_state_all(true).is_err());
+    assert_eq!([1, 2, 1, 1], expander.cycle_counters().cycles);
+
+    expander.set_cycle_counters(CycleCounters {
+        cycles: [100, 200, 300, u32::MAX],
+    });
+    expander.set_state(Pin3, true);
+    expander.write_output_state().unwrap();
+    assert_eq!(u32::MAX, expander.cycle_count(Pin3));
+    assert_eq!(300, expander.cycle_count(Pin2));
+}
+
+#[test]
+fn test_toggle_limit_reject() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::Relaxed));
+    expander.set_state_all(false).unwrap();
+    expander.set_safe_state(SafeState::new().pin(Pin0, SafeLevel::High)).unwrap();
+    expander.set_toggle_limit(ToggleLimit {
+        min_interval: 100,
+        action: ToggleAction::Reject,
+    });
+
+    NOW.store(99, Ordering::Relaxed);
+    let writes = simulator.write_count();
+    expander.set_state(Pin1, true);
+    expander.set_state(Pin2, true);
+    assert_eq!(Err(OutputError::ToggleTooFast(Pin1)), expander.write_output_state());
+    assert_eq!(writes, simulator.write_count());
+    assert!(!expander.is_pin_output_high(Pin1));
+
+    // Safe state is not limited
+    expander.apply_safe_state().unwrap();
+    assert_eq!([true, false, false, false], simulator.pins());
+
+    NOW.store(100, Ordering::Relaxed);
+    expander.set_state(Pin1, true);
+    expander.write_output_state().unwrap();
+    assert_eq!([true, true, false, false], simulator.pins());
+
+    expander.remove_toggle_limit();
+    assert_eq!(None, expander.toggle_limit());
+    expander.set_state_all(false).unwrap();
+}
+
+#[test]
+fn test_toggle_limit_defer() {
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_clock(|| NOW.load(Ordering::Relaxed));
+    expander.set_state_all(false).unwrap();
+    expander.set_toggle_limit(ToggleLimit {
+        min_interval: 100,
+        action: ToggleAction::Defer,
+    });
+
+    NOW.store(10, Ordering::Relaxed);
+    expander.set_state_all(true).unwrap();
+    assert_eq!([false, false, false, false], simulator.pins());
+    assert!(expander.is_pin_output_high(Pin0));
+    assert!(expander.has_pending_changes());
+    assert_eq!([1, 1, 1, 1], expander.cycle_counters().cycles);
+
+    NOW.store(100, Ordering::Relaxed);
+    assert!(expander.flush_pending().unwrap());
+    assert_eq!([true, true, true, true], simulator.pins());
+    assert!(!expander.has_pending_changes());
+    assert_eq!([2, 2, 2, 2], expander.cycle_counters().cycles);
+
+    // Only the pins toggled too fast are deferred
+    NOW.store(200, Ordering::Relaxed);
+    expander.set_state(Pin0, false);
+    expander.write_output_state().unwrap();
+    NOW.store(250, Ordering::Relaxed);
+    expander.set_state(Pin0, true);
+    expander.set_state(Pin1, false);
+    expander.write_output_state().unwrap();
+    assert_eq!([false, false, true, true], simulator.pins());
+    assert!(expander.has_pending_changes());
+}
+
+#[test]
+#[cfg(feature = "embedded-storage")]
+fn test_persist_round_trip() {
+    let simulator = SimulatedPCA9570::default();
+    let mut store = OutputStore::new(SimulatedFlash::<512>::default(), 128);
+    assert_eq!(Ok(None), store.load());
+
+    // Erased flash, so the bus is not accessed
+    let (mut expander, result) = PCA9570::with_store(&simulator, 0x24, &mut store);
+    assert_eq!(Ok(()), result);
+    assert_eq!(0, simulator.write_count());
+
+    expander.set_polarity(Pin2, Polarity::ActiveLow);
+    expander.set_state(Pin0, false);
+    expander.set_state(Pin2, true);
+    expander.write_output_state().unwrap();
+    expander.save_state(&mut store).unwrap();
+    expander.save_state(&mut store).unwrap();
+
+    let state = StoredState {
+        output: 0b1111_1010,
+        cycles: CycleCounters { cycles: [1, 0, 1, 0] },
+    };
+    assert_eq!(Ok(Some(state)), store.load());
+    drop(expander);
+
+    simulator.power_cycle();
+    let (expander, result) = PCA9570::with_store(&simulator, 0x24, &mut store);
+    assert_eq!(Ok(()), result);
+    assert_eq!([false, true, false, true], simulator.pins());
+    assert_eq!([2, 0, 2, 0], expander.cycle_counters().cycles);
+
+    let flash = store.into_inner();
+    assert_eq!(2, flash.erase_count());
+    assert!(flash.data()[..128].iter().all(|byte| *byte == 0xFF));
+    assert_eq!([0x95, 0x70, LAYOUT_VERSION, 0b1111_1010], flash.data()[128..132]);
+}
+
+#[test]
+#[cfg(feature = "embedded-storage")]
+fn test_persist_corrupt() {
+    let mut store = OutputStore::new(SimulatedFlash::<256>::default(), 0);
+    store.save(&StoredState::default()).unwrap();
+
+    let mut flash = store.into_inner();
+    flash.data_mut()[5] ^= 0x1;
+    let mut store = OutputStore::new(flash, 0);
+    assert_eq!(Err(StoreError::Corrupt), store.load());
+
+    // Driver starts with the power-on state
+    let simulator = SimulatedPCA9570::default();
+    let (expander, result) = PCA9570::with_store(&simulator, 0x24, &mut store);
+    assert_eq!(Err(StoreError::Corrupt), result);
+    assert_eq!(0, simulator.write_count());
+    assert_eq!(0, expander.cycle_count(Pin0));
+    drop(expander);
+
+    let mut flash = store.into_inner();
+    flash.data_mut()[0] = 0x0;
+    let mut store = OutputStore::new(flash, 0);
+    assert_eq!(Err(StoreError::Corrupt), store.load());
+
+    store.clear().unwrap();
+    assert_eq!(Ok(None), store.load());
+}
+
+#[test]
+#[cfg(feature = "embedded-storage")]
+fn test_persist_unsupported_version() {
+    let mut store = OutputStore::new(SimulatedFlash::<256>::default(), 0);
+    store.save(&StoredState::default()).unwrap();
+
+    let mut flash = store.into_inner();
+    flash.data_mut()[2] = 2;
+    let mut store = OutputStore::new(flash, 0);
+    assert_eq!(Err(StoreError::UnsupportedVersion(2)), store.load());
+}
+
+#[test]
+#[cfg(feature = "embedded-storage")]
+fn test_persist_flash_errors() {
+    let mut store = OutputStore::new(SimulatedFlash::<256>::default(), 64);
+    assert_eq!(
+        Err(StoreError::Flash(NorFlashErrorKind::NotAligned)),
+        store.save(&StoredState::default())
+    );
+
+    let mut store = OutputStore::new(SimulatedFlash::<256>::default(), 256);
+    assert_eq!(Err(StoreError::Flash(NorFlashErrorKind::OutOfBounds)), store.load());
+}
+
+#[test]
+#[cfg(feature = "embedded-storage")]
+fn test_persist_restore_bus_error() {
+    let mut store = OutputStore::new(SimulatedFlash::<256>::default(), 0);
+    store.save(&StoredState::default()).unwrap();
+
+    let bus = FaultyBus::new(SimulatedPCA9570::default()).nack_write(1).unwrap();
+    let (mut expander, result) = PCA9570::with_store(bus, 0x24, &mut store);
+    assert_eq!(
+        Err(StoreError::BusError(OutputError::WriteError(FaultError::Nack))),
+        result
+    );
+
+    // Restored state is still cached and written by the next attempt
+    expander.write_output_state().unwrap();
+    assert_eq!(0b0000_0000, expander.destroy().into_inner().output());
+}
+
+#[test]
+#[cfg(feature = "embedded-storage")]
+fn test_persist_torn_record() {
+    let mut store = OutputStore::new(SimulatedFlash::<256>::default(), 0);
+    store.save(&StoredState::default()).unwrap();
+
+    // Power loss after erasing and writing the first word
+    let mut flash = store.into_inner();
+    flash.data_mut()[4..24].fill(0xFF);
+    let mut store = OutputStore::new(flash, 0);
+
+    let simulator = SimulatedPCA9570::default();
+    let (mut expander, result) = PCA9570::with_store(&simulator, 0x24, &mut store);
+    assert_eq!(Err(StoreError::Corrupt), result);
+    assert_eq!([true, true, true, true], simulator.pins());
+
+    // Saving again repairs the record
+    expander.set_state_all(false).unwrap();
+    expander.save_state(&mut store).unwrap();
+    drop(expander);
+
+    let (_, result) = PCA9570::with_store(&simulator, 0x24, &mut store);
+    assert_eq!(Ok(()), result);
+}
+
+#[test]
+fn test_scenes() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_polarity(Pin1, Polarity::ActiveLow);
+    expander.add_scene(Scene::new("idle", 0b0000).unwrap()).unwrap();
+    expander.add_scene(Scene::new("fill", 0b0011).unwrap()).unwrap();
+    expander.add_scene(Scene::masked("drain", 0b0100, 0b1100).unwrap()).unwrap();
+    assert_eq!(None, expander.current_scene());
+
+    expander.apply_scene("fill").unwrap();
+    assert_eq!(1, simulator.write_count());
+    assert_eq!([true, false, false, false], simulator.pins());
+    assert_eq!(Some("fill"), expander.current_scene());
+
+    // Masked scene keeps the other pins
+    expander.apply_scene("drain").unwrap();
+    assert_eq!([true, false, true, false], simulator.pins());
+    assert_eq!(Some("drain"), expander.current_scene());
+
+    expander.set_state(Pin3, true);
+    assert_eq!(None, expander.current_scene());
+
+    expander.apply_scene("idle").unwrap();
+    assert_eq!(Some("idle"), expander.current_scene());
+
+    // Replaced by name
+    expander.add_scene(Scene::new("fill", 0b0001).unwrap()).unwrap();
+    assert_eq!(3, expander.scenes().count());
+    assert_eq!(0b0001, expander.scene("fill").unwrap().pattern());
+
+    assert_eq!(Err(SceneError::UnknownScene), expander.apply_scene("flush"));
+    expander.clear_scenes();
+    assert_eq!(None, expander.current_scene());
+}
+
+#[test]
+fn test_scene_interlock() {
+    let simulator = SimulatedPCA9570::default();
+    let mut expander = PCA9570::new(&simulator, 0x24);
+    expander.set_state_all(false).unwrap();
+    expander.add_interlock(Interlock::requires(&[Pin0], &[Pin1])).unwrap();
+    expander.add_scene(Scene::new("pump", 0b0001).unwrap()).unwrap();
+    expander.add_scene(Scene::new("flow", 0b0011).unwrap()).unwrap();
+
+    let writes = simulator.write_count();
+    assert_eq!(
+        Err(SceneError::OutputError(OutputError::Interlock(Interlock::Requires(
+            0b01, 0b10
+        )))),
+        expander.apply_scene("pump")
+    );
+    assert_eq!(writes, simulator.write_count());
+    assert_eq!(None, expander.current_scene());
+
+    // Both pins are switched by the same write
+    expander.apply_scene("flow").unwrap();
+    assert_eq!(writes + 1, simulator.write_count());
+    assert_eq!([true, true, false, false], simulator.pins());
+}
+
+#[test]
+fn test_scene_new() {
+    assert_eq!(None, Scene::new("", 0b0000));
+    assert_eq!(None, Scene::new("two words", 0b0000));
+
+    let scene = Scene::masked("fill", 0b1111_0111, 0b0000_0110).unwrap();
+    assert_eq!("fill", scene.name());
+    assert_eq!(0b0110, scene.pattern());
+    assert_eq!(0b0110, scene.mask());
+    assert!(scene.matches(0b1110));
+    assert!(!scene.matches(0b1010));
+    assert_eq!(0b1111, scene.apply_to(0b1001));
+}
+
+#[test]
+fn test_scene_capacity() {
+    let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+
+    let names = ["s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8"];
+
+    for name in &names[..MAX_SCENES] {
+        expander.add_scene(Scene::new(name, 0b0000).unwrap()).unwrap();
+    }
+
+    assert_eq!(
+        Err(CapacityError),
+        expander.add_scene(Scene::new(names[MAX_SCENES], 0b0000).unwrap())
+    );
+
+    // Replacing a scene needs no free slot
+    expander.add_scene(Scene::new("s0", 0b0001).unwrap()).unwrap();
+    assert_eq!(MAX_SCENES, expander.scenes().count());
+}
+
 /// Testing spin based RefGuard
 #[cfg(feature = "spin")]
-fn get_pins(expander: &mut PCA9570<MockI2CBus>) -> Pins<MockI2CBus, SpinGuard<MockI2CBus>> {
+fn get_pins(expander: &mut PCA9570<MockI2CBus>) -> Pins<MockI2CBus, SpinGuard<'_, MockI2CBus>> {
     expander.pins_spin_mutex()
 }
 
 /// Testing lock-free RefGuard
 #[cfg(not(feature = "spin"))]
-fn get_pins(expander: &mut PCA9570<MockI2CBus>) -> Pins<MockI2CBus, LockFreeGuard<MockI2CBus>> {
+fn get_pins(expander: &mut PCA9570<MockI2CBus>) -> Pins<MockI2CBus, LockFreeGuard<'_, MockI2CBus>> {
     expander.pins()
 }
@@ -0,0 +1,478 @@
+//! # Transaction traces
+//!
+//! [RecordingBus] wraps any I2C bus and records each transaction into a [Trace]. The trace can be stored as
+//! plain text, e.g. as golden file of a regression test, and served again by [ReplayBus], which fails on any
+//! deviation from the recorded traffic.
+//!
+//! ## Text format
+//! Each line describes one transaction, fields are separated by whitespace:
+//! ```text
+//! # timestamp direction address bytes result
+//! 1500 W 0x24 0F OK
+//! 1510 W 0x24 - OK
+//! 1510 R 0x24 F5 OK
+//! - W 0x24 0E ERR
+//! ```
+//! * Timestamp in ticks of the [Clock], `-` if no clock is set
+//! * Direction, `W` for writes and `R` for reads
+//! * 7-bit address
+//! * Written or read bytes in hex, `-` if there are none
+//! * Result, either `OK` or `ERR`. The data of failed reads is not recorded.
+//!
+//! Empty lines and lines starting with `#` are ignored.
+//!
+//! ## Example
+//! ```
+//! use pca9570::expander::PCA9570;
+//! use pca9570::expander::PinID::Pin0;
+//! use pca9570::sim::SimulatedPCA9570;
+//! use pca9570::trace::{RecordingBus, ReplayBus, Trace};
+//!
+//! fn application<B>(expander: &mut PCA9570<B>) -> Result<(), ()>
+//! where
+//!     B: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::Read,
+//! {
+//!     expander.set_state_all(false).map_err(|_| ())?;
+//!     expander.set_state(Pin0, true);
+//!     expander.write_output_state().map_err(|_| ())
+//! }
+//!
+//! // Record the traffic once
+//! let mut expander = PCA9570::new(RecordingBus::new(SimulatedPCA9570::default()), 0x24);
+//! application(&mut expander).unwrap();
+//!
+//! let golden = expander.destroy().trace().to_string();
+//! assert_eq!("- W 0x24 00 OK\n- W 0x24 01 OK\n", golden);
+//!
+//! // Replay it in the regression test
+//! let bus = ReplayBus::new(golden.parse::<Trace>().unwrap());
+//! let mut expander = PCA9570::new(&bus, 0x24);
+//! application(&mut expander).unwrap();
+//!
+//! assert_eq!(Ok(()), bus.finish());
+//! ```
+use crate::expander::Clock;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
+use embedded_hal::blocking::i2c::{Read, SevenBitAddress, Write};
+
+/// Direction of an I2C transaction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// Write transaction, `W` in the text format
+    Write,
+
+    /// Read transaction, `R` in the text format
+    Read,
+}
+
+/// Recorded I2C transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Timestamp of the transaction, if a clock is set
+    pub timestamp: Option<u64>,
+
+    /// Direction of the transaction
+    pub direction: Direction,
+
+    /// 7-bit I2C address
+    pub address: u8,
+
+    /// Written or read bytes, empty for failed reads
+    pub bytes: Vec<u8>,
+
+    /// True if the transaction was successful
+    pub ok: bool,
+}
+
+/// Sequence of recorded I2C transactions
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Trace {
+    entries: Vec<TraceEntry>,
+}
+
+/// Error while parsing a textual trace
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseTraceError {
+    /// Line number of the invalid line, starting with 1
+    pub line: usize,
+}
+
+/// Errors returned by [ReplayBus]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The recorded transaction failed, so the replayed one fails as well
+    Recorded,
+
+    /// The transaction deviates from the trace
+    Deviation {
+        /// Index of the expected trace entry
+        index: usize,
+
+        /// Actual transaction, without timestamp. The data of reads is unknown and left empty.
+        actual: TraceEntry,
+    },
+
+    /// Not all recorded transactions were replayed
+    Incomplete {
+        /// Number of remaining trace entries
+        remaining: usize,
+    },
+}
+
+impl Trace {
+    /// Creates an empty trace
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the recorded transactions
+    pub fn entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+
+    /// Appends a transaction
+    pub fn push(&mut self, entry: TraceEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Removes all transactions
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the number of transactions
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no transactions are recorded
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.timestamp {
+            None => f.write_str("-")?,
+            Some(timestamp) => write!(f, "{}", timestamp)?,
+        }
+
+        match self.direction {
+            Direction::Write => f.write_str(" W")?,
+            Direction::Read => f.write_str(" R")?,
+        }
+
+        write!(f, " {:#04x} ", self.address)?;
+
+        if self.bytes.is_empty() {
+            f.write_str("-")?;
+        }
+
+        for (i, byte) in self.bytes.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+
+        f.write_str(if self.ok { " OK" } else { " ERR" })
+    }
+}
+
+impl Display for Trace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TraceEntry {
+    /// Parses a single line of the text format
+    fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if fields.len() < 5 {
+            return None;
+        }
+
+        let timestamp = match fields[0] {
+            "-" => None,
+            timestamp => Some(timestamp.parse().ok()?),
+        };
+
+        let direction = match fields[1] {
+            "W" => Direction::Write,
+            "R" => Direction::Read,
+            _ => return None,
+        };
+
+        let address = u8::from_str_radix(fields[2].trim_start_matches("0x"), 16).ok()?;
+
+        let bytes = match &fields[3..fields.len() - 1] {
+            ["-"] => Vec::new(),
+            data => data
+                .iter()
+                .map(|byte| u8::from_str_radix(byte, 16).ok())
+                .collect::<Option<_>>()?,
+        };
+
+        let ok = match fields[fields.len() - 1] {
+            "OK" => true,
+            "ERR" => false,
+            _ => return None,
+        };
+
+        Some(Self {
+            timestamp,
+            direction,
+            address,
+            bytes,
+            ok,
+        })
+    }
+}
+
+impl FromStr for Trace {
+    type Err = ParseTraceError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut trace = Trace::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            trace.push(TraceEntry::parse(line).ok_or(ParseTraceError { line: i + 1 })?);
+        }
+
+        Ok(trace)
+    }
+}
+
+impl Display for ParseTraceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Invalid trace entry in line {}", self.line)
+    }
+}
+
+/// I2C bus wrapper recording each transaction
+pub struct RecordingBus<B> {
+    bus: B,
+
+    trace: Trace,
+
+    clock: Option<Clock>,
+}
+
+impl<B> RecordingBus<B> {
+    /// Wraps the given bus without recording timestamps
+    pub fn new(bus: B) -> Self {
+        Self {
+            bus,
+            trace: Trace::new(),
+            clock: None,
+        }
+    }
+
+    /// Records the timestamp of each transaction using the given clock
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Returns the recorded trace
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// Removes all recorded transactions
+    pub fn clear(&mut self) {
+        self.trace.clear();
+    }
+
+    /// Returns a reference to the inner bus
+    pub fn inner(&self) -> &B {
+        &self.bus
+    }
+
+    /// Returns the inner bus and the recorded trace
+    pub fn into_parts(self) -> (B, Trace) {
+        (self.bus, self.trace)
+    }
+
+    fn record(&mut self, direction: Direction, address: u8, bytes: &[u8], ok: bool) {
+        let timestamp = self.clock.map(|clock| clock());
+
+        self.trace.push(TraceEntry {
+            timestamp,
+            direction,
+            address,
+            bytes: bytes.to_vec(),
+            ok,
+        });
+    }
+}
+
+impl<B: Write> Write for RecordingBus<B> {
+    type Error = B::Error;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        let result = self.bus.write(address, bytes);
+        self.record(Direction::Write, address, bytes, result.is_ok());
+        result
+    }
+}
+
+impl<B: Read> Read for RecordingBus<B> {
+    type Error = B::Error;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        let result = self.bus.read(address, buffer);
+
+        match result {
+            Ok(_) => self.record(Direction::Read, address, buffer, true),
+            Err(_) => self.record(Direction::Read, address, &[], false),
+        }
+
+        result
+    }
+}
+
+/// I2C bus serving a recorded trace, failing on any deviation.
+/// Timestamps are not compared.
+pub struct ReplayBus {
+    trace: Trace,
+
+    /// Index of the next trace entry
+    next: Cell<usize>,
+
+    /// First deviation from the trace
+    deviation: RefCell<Option<ReplayError>>,
+}
+
+impl ReplayBus {
+    /// Creates a bus serving the given trace
+    pub fn new(trace: Trace) -> Self {
+        Self {
+            trace,
+            next: Cell::new(0),
+            deviation: RefCell::new(None),
+        }
+    }
+
+    /// Returns the number of transactions not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.trace.len().saturating_sub(self.next.get())
+    }
+
+    /// Returns the first deviation from the trace, or [ReplayError::Incomplete] if transactions are left
+    pub fn finish(&self) -> Result<(), ReplayError> {
+        if let Some(deviation) = self.deviation.borrow().clone() {
+            return Err(deviation);
+        }
+
+        match self.remaining() {
+            0 => Ok(()),
+            remaining => Err(ReplayError::Incomplete { remaining }),
+        }
+    }
+
+    fn write_bytes(&self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), ReplayError> {
+        self.replay(Direction::Write, address, bytes, |recorded| recorded.bytes == bytes)
+            .map(|_| ())
+    }
+
+    fn read_bytes(&self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), ReplayError> {
+        // Failed reads carry no data, so the length is only checked for successful ones
+        let recorded = self.replay(Direction::Read, address, &[], |recorded| {
+            !recorded.ok || recorded.bytes.len() == buffer.len()
+        })?;
+
+        buffer.copy_from_slice(&recorded.bytes);
+        Ok(())
+    }
+
+    /// Compares the transaction with the next trace entry and returns the entry if successful
+    fn replay(
+        &self,
+        direction: Direction,
+        address: u8,
+        bytes: &[u8],
+        matches: impl FnOnce(&TraceEntry) -> bool,
+    ) -> Result<&TraceEntry, ReplayError> {
+        let index = self.next.get();
+
+        let recorded = self
+            .trace
+            .entries()
+            .get(index)
+            .filter(|recorded| recorded.direction == direction && recorded.address == address)
+            .filter(|recorded| matches(recorded));
+
+        let Some(recorded) = recorded else {
+            let deviation = ReplayError::Deviation {
+                index,
+                actual: TraceEntry {
+                    timestamp: None,
+                    direction,
+                    address,
+                    bytes: bytes.to_vec(),
+                    ok: false,
+                },
+            };
+
+            self.deviation.borrow_mut().get_or_insert_with(|| deviation.clone());
+            return Err(deviation);
+        };
+
+        self.next.set(index + 1);
+
+        if !recorded.ok {
+            return Err(ReplayError::Recorded);
+        }
+
+        Ok(recorded)
+    }
+}
+
+impl Write for ReplayBus {
+    type Error = ReplayError;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_bytes(address, bytes)
+    }
+}
+
+impl Read for ReplayBus {
+    type Error = ReplayError;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_bytes(address, buffer)
+    }
+}
+
+impl Write for &ReplayBus {
+    type Error = ReplayError;
+
+    fn write(&mut self, address: SevenBitAddress, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_bytes(address, bytes)
+    }
+}
+
+impl Read for &ReplayBus {
+    type Error = ReplayError;
+
+    fn read(&mut self, address: SevenBitAddress, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.read_bytes(address, buffer)
+    }
+}
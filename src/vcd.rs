@@ -0,0 +1,200 @@
+//! # Value Change Dump export
+//!
+//! [VcdRecorder] is an [Observer] of [PCA9570](crate::expander::PCA9570) and records each committed
+//! change of the output register, i.e. each successful write changing its value, with a timestamp of the
+//! user supplied [Clock]. This includes pins released by switching them to input mode. The history
+//! can be exported as [VCD](https://en.wikipedia.org/wiki/Value_change_dump) file with one signal per pin,
+//! e.g. for viewing the sequencing in GTKWave or PulseView.
+//!
+//! * Pins are named P0 to P3 by default, other labels can be configured. Labels must not contain whitespace.
+//! * The timescale defaults to 1 ms and needs to match the unit of the clock ticks
+//! * Pins are undefined (`x`) until the first write, unless it happens at timestamp 0
+//! * Timestamps are expected to be monotonic
+//! * Further observers are attached together with the recorder by [Fanout](crate::observer::Fanout)
+//!
+//! ```
+//! use core::sync::atomic::{AtomicU64, Ordering};
+//! use pca9570::expander::PCA9570;
+//! use pca9570::expander::PinID::Pin0;
+//! use pca9570::sim::SimulatedPCA9570;
+//! use pca9570::vcd::VcdRecorder;
+//!
+//! static NOW: AtomicU64 = AtomicU64::new(0);
+//!
+//! let recorder = VcdRecorder::new(|| NOW.load(Ordering::Relaxed))
+//!     .with_labels(["relay", "led", "P2", "P3"])
+//!     .unwrap()
+//!     .with_timescale("1 us");
+//! let recorder: &'static VcdRecorder = Box::leak(Box::new(recorder));
+//!
+//! let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+//! expander.set_observer(recorder);
+//!
+//! NOW.store(10, Ordering::Relaxed);
+//! expander.set_state_all(false).unwrap();
+//!
+//! NOW.store(25, Ordering::Relaxed);
+//! expander.set_state(Pin0, true);
+//! expander.write_output_state().unwrap();
+//!
+//! let vcd = recorder.to_vcd();
+//! assert!(vcd.contains("$var wire 1 ! relay $end"));
+//! assert!(vcd.ends_with("#25\n1!\n"));
+//! ```
+use crate::expander::{Clock, OutputError, OUTPUT_MASK};
+use crate::observer::Observer;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use embedded_hal::blocking::i2c::{Read, Write};
+
+/// Committed value of the output register
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OutputChange {
+    /// Timestamp in ticks of the clock
+    pub timestamp: u64,
+
+    /// 4-bit output register
+    pub value: u8,
+}
+
+/// Observer recording the history of the output register
+pub struct VcdRecorder {
+    clock: Clock,
+
+    labels: [&'static str; 4],
+
+    timescale: &'static str,
+
+    history: RefCell<Vec<OutputChange>>,
+}
+
+impl VcdRecorder {
+    /// Creates the recorder, using the clock for timestamps
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            labels: ["P0", "P1", "P2", "P3"],
+            timescale: "1 ms",
+            history: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Sets the signal names, starting with Pin0
+    /// Returns None if a label is empty or contains whitespace, as it would break the VCD file.
+    pub fn with_labels(mut self, labels: [&'static str; 4]) -> Option<Self> {
+        if labels
+            .iter()
+            .any(|label| label.is_empty() || label.contains(char::is_whitespace))
+        {
+            return None;
+        }
+
+        self.labels = labels;
+        Some(self)
+    }
+
+    /// Sets the VCD timescale, e.g. "1 us", matching the unit of the clock ticks
+    pub fn with_timescale(mut self, timescale: &'static str) -> Self {
+        self.timescale = timescale;
+        self
+    }
+
+    /// Returns a copy of the recorded changes of the output register
+    pub fn history(&self) -> Vec<OutputChange> {
+        self.history.borrow().clone()
+    }
+
+    /// Removes the recorded history
+    pub fn clear(&self) {
+        self.history.borrow_mut().clear();
+    }
+
+    /// Writes the history in VCD format
+    pub fn write_vcd<W: core::fmt::Write>(&self, out: &mut W) -> core::fmt::Result {
+        writeln!(out, "$timescale {} $end", self.timescale)?;
+        writeln!(out, "$scope module pca9570 $end")?;
+        for (pin, label) in self.labels.iter().enumerate() {
+            writeln!(out, "$var wire 1 {} {} $end", Self::identifier(pin), label)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+
+        // Initial values are undefined, unless the first change happened right at the start
+        let history = self.history.borrow();
+        let (initial, changes) = match history.split_first() {
+            Some((first, changes)) if first.timestamp == 0 => (Some(first.value), changes),
+            _ => (None, &history[..]),
+        };
+
+        writeln!(out, "#0")?;
+        writeln!(out, "$dumpvars")?;
+        for pin in 0..self.labels.len() {
+            match initial {
+                Some(value) => writeln!(out, "{}{}", Self::level(value, pin), Self::identifier(pin))?,
+                None => writeln!(out, "x{}", Self::identifier(pin))?,
+            }
+        }
+        writeln!(out, "$end")?;
+
+        let mut previous = initial;
+        for change in changes {
+            writeln!(out, "#{}", change.timestamp)?;
+
+            for pin in 0..self.labels.len() {
+                if previous.map(|value| Self::level(value, pin)) != Some(Self::level(change.value, pin)) {
+                    writeln!(out, "{}{}", Self::level(change.value, pin), Self::identifier(pin))?;
+                }
+            }
+
+            previous = Some(change.value);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the history in VCD format
+    pub fn to_vcd(&self) -> String {
+        let mut vcd = String::new();
+        // Writing to a string does not fail
+        let _ = self.write_vcd(&mut vcd);
+        vcd
+    }
+
+    /// Records the committed value, if it changes the output register
+    fn record(&self, value: u8) {
+        let value = value & OUTPUT_MASK;
+        let mut history = self.history.borrow_mut();
+
+        if history.last().map(|change| change.value) == Some(value) {
+            return;
+        }
+
+        history.push(OutputChange {
+            timestamp: (self.clock)(),
+            value,
+        });
+    }
+
+    /// Short VCD identifier of the given pin
+    fn identifier(pin: usize) -> char {
+        (b'!' + pin as u8) as char
+    }
+
+    fn level(value: u8, pin: usize) -> char {
+        if value & (1 << pin) != 0 {
+            '1'
+        } else {
+            '0'
+        }
+    }
+}
+
+impl<B: Write + Read> Observer<B> for VcdRecorder {
+    fn on_write(&self, _old: u8, new: u8, result: Result<(), &OutputError<B>>) {
+        if result.is_ok() {
+            self.record(new);
+        }
+    }
+}
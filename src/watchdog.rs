@@ -0,0 +1,113 @@
+//! # Watchdog
+//!
+//! In watchdog mode, the application needs to call [PCA9570::feed()](crate::expander::PCA9570::feed)
+//! within the configured timeout. Otherwise, the next call of [PCA9570::poll()](crate::expander::PCA9570::poll)
+//! writes the safe state, or deasserts all outputs if no safe state is configured. This falls back to a safe
+//! state, e.g. if a task hangs, as long as polling is done independently, e.g. from a timer interrupt.
+//!
+//! The fault is latched, so all further writes are rejected with
+//! [OutputError::WatchdogFault](crate::expander::OutputError::WatchdogFault) until the fault is cleared
+//! explicitly. Timestamps are taken from the user supplied clock, s.
+//! [PCA9570::set_clock()](crate::expander::PCA9570::set_clock).
+//!
+//! Optionally, a heartbeat pin is toggled on each feed, e.g. for an external supervisor.
+//! ```
+//! use core::sync::atomic::{AtomicU64, Ordering};
+//! use pca9570::expander::Mode::Output;
+//! use pca9570::expander::PinID::Pin3;
+//! use pca9570::expander::PCA9570;
+//! use pca9570::sim::SimulatedPCA9570;
+//! use pca9570::watchdog::{Watchdog, WatchdogStatus};
+//!
+//! static NOW: AtomicU64 = AtomicU64::new(0);
+//!
+//! let simulator = SimulatedPCA9570::default();
+//! let mut expander = PCA9570::new(&simulator, 0x24);
+//! expander.set_clock(|| NOW.load(Ordering::Relaxed));
+//! expander.set_mode_all(Output).unwrap();
+//! expander.set_state_all(true).unwrap();
+//!
+//! expander.enable_watchdog(Watchdog {
+//!     timeout: 100,
+//!     heartbeat: Some(Pin3),
+//! });
+//!
+//! NOW.store(80, Ordering::Relaxed);
+//! expander.feed().unwrap();
+//! assert_eq!(WatchdogStatus::Running, expander.poll().unwrap());
+//!
+//! // Application missed the deadline
+//! NOW.store(200, Ordering::Relaxed);
+//! assert_eq!(WatchdogStatus::Expired, expander.poll().unwrap());
+//! assert_eq!([false, false, false, false], simulator.pins());
+//!
+//! assert!(expander.set_state_all(true).is_err());
+//! expander.clear_watchdog_fault();
+//! expander.set_state_all(true).unwrap();
+//! ```
+use crate::expander::PinID;
+
+/// Watchdog settings
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Watchdog {
+    /// Maximum number of clock ticks between two feeds
+    pub timeout: u64,
+
+    /// Pin toggled on each feed
+    pub heartbeat: Option<PinID>,
+}
+
+/// Result of [PCA9570::poll()](crate::expander::PCA9570::poll)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WatchdogStatus {
+    /// Watchdog mode is not enabled
+    Disabled,
+
+    /// Watchdog was fed in time
+    Running,
+
+    /// Deadline was missed, so the safe state was written by this poll.
+    /// If writing failed, the poll returns the error and the next poll retries.
+    Expired,
+
+    /// Fault is latched since an earlier poll
+    Faulted,
+}
+
+/// Watchdog settings and state of a single expander
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct WatchdogState {
+    pub(crate) watchdog: Watchdog,
+
+    /// Timestamp of the last feed
+    pub(crate) fed_at: Option<u64>,
+
+    /// True if the deadline was missed
+    pub(crate) latched: bool,
+
+    /// True if writing the safe state failed, so it is retried by the next poll
+    pub(crate) fallback_pending: bool,
+}
+
+impl WatchdogState {
+    pub(crate) fn new(watchdog: Watchdog, now: Option<u64>) -> Self {
+        Self {
+            watchdog,
+            fed_at: now,
+            latched: false,
+            fallback_pending: false,
+        }
+    }
+
+    /// Returns true if the deadline is missed
+    /// Without a clock, the watchdog never expires.
+    pub(crate) fn is_expired(&self, now: Option<u64>) -> bool {
+        match (self.fed_at, now) {
+            (Some(fed_at), Some(now)) => now.saturating_sub(fed_at) > self.watchdog.timeout,
+            _ => false,
+        }
+    }
+}
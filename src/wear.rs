@@ -0,0 +1,76 @@
+//! # Wear management
+//!
+//! Mechanical relays have a finite switching life. The driver counts the transitions of each pin, updated
+//! only when a write actually changes the bit on the device. Counters are exported by
+//! [PCA9570::cycle_counters()](crate::expander::PCA9570::cycle_counters), e.g. for persisting them, and
+//! restored by [PCA9570::set_cycle_counters()](crate::expander::PCA9570::set_cycle_counters).
+//!
+//! Optionally, a minimum interval between two transitions of the same pin is enforced, s.
+//! [PCA9570::set_toggle_limit()](crate::expander::PCA9570::set_toggle_limit). Transitions coming too fast are
+//! either rejected or deferred until the next flush. Timestamps are taken from the user supplied clock, s.
+//! [PCA9570::set_clock()](crate::expander::PCA9570::set_clock). Writes of the safe state are not limited.
+//! ```
+//! use core::sync::atomic::{AtomicU64, Ordering};
+//! use pca9570::expander::PinID::Pin0;
+//! use pca9570::expander::{OutputError, PCA9570};
+//! use pca9570::sim::SimulatedPCA9570;
+//! use pca9570::wear::{ToggleAction, ToggleLimit};
+//!
+//! static NOW: AtomicU64 = AtomicU64::new(0);
+//!
+//! let mut expander = PCA9570::new(SimulatedPCA9570::default(), 0x24);
+//! expander.set_clock(|| NOW.load(Ordering::Relaxed));
+//! expander.set_toggle_limit(ToggleLimit {
+//!     min_interval: 100,
+//!     action: ToggleAction::Reject,
+//! });
+//!
+//! expander.set_state(Pin0, false);
+//! expander.write_output_state().unwrap();
+//! assert_eq!(1, expander.cycle_count(Pin0));
+//!
+//! NOW.store(50, Ordering::Relaxed);
+//! expander.set_state(Pin0, true);
+//! assert_eq!(Err(OutputError::ToggleTooFast(Pin0)), expander.write_output_state());
+//!
+//! NOW.store(100, Ordering::Relaxed);
+//! expander.set_state(Pin0, true);
+//! expander.write_output_state().unwrap();
+//! assert_eq!(2, expander.cycle_count(Pin0));
+//! ```
+
+/// Switching cycles of all pins, s. [module](crate::wear)
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CycleCounters {
+    /// Number of transitions, starting with Pin0
+    pub cycles: [u32; 4],
+}
+
+/// Minimum interval between two transitions of the same pin
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ToggleLimit {
+    /// Minimum number of clock ticks between two transitions
+    pub min_interval: u64,
+
+    /// Handling of transitions coming too fast
+    pub action: ToggleAction,
+}
+
+/// Handling of transitions violating the [ToggleLimit]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ToggleAction {
+    /// Write is rejected with [OutputError::ToggleTooFast](crate::expander::OutputError::ToggleTooFast)
+    /// without touching the bus
+    Reject,
+
+    /// Other pins are written, while the affected pins keep their device state. The changes stay pending
+    /// and are written by a later write or flush, s.
+    /// [PCA9570::flush_pending()](crate::expander::PCA9570::flush_pending).
+    Defer,
+}